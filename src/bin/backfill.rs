@@ -0,0 +1,44 @@
+use std::env;
+use std::process;
+use std::sync::Arc;
+
+use lfb_back::*;
+use rocket::tokio::runtime::Runtime;
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let dry_run = args.iter().any(|x| x == "--dry-run");
+    args.retain(|x| x != "--dry-run");
+    let blocks: Result<Vec<u64>, _> = args.iter().take(2).map(|x| x.parse()).collect();
+    let (from, to) = match blocks.as_deref() {
+        Ok(&[from, to]) if from <= to && args.len() <= 3 => (from, to),
+        _ => {
+            eprintln!("usage: backfill <from_block> <to_block> [chain] [--dry-run]");
+            process::exit(1);
+        }
+    };
+    // the chain is one of CHAINS, the unprefixed variables are used without it
+    let config = match args.get(2) {
+        Some(chain) => IndexerConfig::from_chain(chain)
+            .unwrap_or_else(|| panic!("{}_RPC_URL must be set", chain.to_uppercase())),
+        None => IndexerConfig::from_env().expect("RPC_URL must be set"),
+    };
+    let runtime = Runtime::new().unwrap();
+    let db = runtime
+        .block_on(MongoRep::init(
+            dotenv::var("MONGO_URI").expect("MONGO_URI must be set"),
+            "lfb",
+        ))
+        .map(Arc::new)
+        .unwrap();
+    let mut indexer = Indexer::new(db, runtime.handle().clone(), config)
+        .unwrap()
+        .with_dry_run(dry_run);
+    match indexer.backfill(from, to) {
+        Ok(applied) => println!("applied {} logs from block {} to {}", applied, from, to),
+        Err(e) => {
+            eprintln!("could not backfill recipes: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::env;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::Client;
+
+// routes reading the whole recipes collection, the slowest ones under load
+const PATHS: [&str; 2] = ["/leaderboard", "/ongoing-recipes"];
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let numbers: Result<Vec<u64>, _> = args.iter().skip(1).map(|x| x.parse()).collect();
+    let (url, concurrency, seconds) = match (args.first(), numbers.as_deref()) {
+        (Some(url), Ok(&[])) => (url, 64, 10),
+        (Some(url), Ok(&[concurrency])) => (url, concurrency, 10),
+        (Some(url), Ok(&[concurrency, seconds])) => (url, concurrency, seconds),
+        _ => {
+            eprintln!("usage: load_test <base_url> [concurrency] [seconds]");
+            process::exit(1);
+        }
+    };
+    for path in PATHS {
+        let (mut latencies, failed) = run(&format!("{}{}", url, path), concurrency, seconds);
+        latencies.sort();
+        println!(
+            "{}: {:.1} req/s, p50 {:.1}ms, p99 {:.1}ms, {} ok, {} failed with {} clients over {}s",
+            path,
+            latencies.len() as f64 / seconds as f64,
+            percentile(&latencies, 50),
+            percentile(&latencies, 99),
+            latencies.len(),
+            failed,
+            concurrency,
+            seconds
+        );
+    }
+}
+
+// latency in milliseconds under which `percent` of the sorted `latencies` fall
+fn percentile(latencies: &[Duration], percent: usize) -> f64 {
+    if latencies.is_empty() {
+        return 0.0;
+    }
+    let index = (latencies.len() * percent).div_ceil(100).max(1) - 1;
+    latencies[index].as_secs_f64() * 1000.0
+}
+
+// every client sends its next request as soon as the previous one returned,
+// returns the latencies of the successful requests and the number of failed ones
+fn run(url: &str, concurrency: u64, seconds: u64) -> (Vec<Duration>, u64) {
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let clients: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let url = url.to_string();
+            thread::spawn(move || {
+                let client = Client::new();
+                let mut latencies = vec![];
+                let mut failed = 0;
+                while Instant::now() < deadline {
+                    let start = Instant::now();
+                    match client.get(&url).send() {
+                        Ok(response) if response.status().is_success() => {
+                            latencies.push(start.elapsed())
+                        }
+                        _ => failed += 1,
+                    };
+                }
+                (latencies, failed)
+            })
+        })
+        .collect();
+    let mut latencies = vec![];
+    let mut failed = 0;
+    for client in clients {
+        let (client_latencies, client_failed) = client.join().unwrap();
+        latencies.extend(client_latencies);
+        failed += client_failed;
+    }
+    (latencies, failed)
+}
@@ -0,0 +1,46 @@
+use std::env;
+use std::process;
+use std::sync::Arc;
+
+use lfb_back::*;
+use rocket::tokio::runtime::Runtime;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let status = match args.first().map(|x| x.as_str()) {
+        Some("pause") => Status::Paused,
+        Some("resume") => Status::Ongoing,
+        Some("cancel") => Status::Cancelled,
+        _ => usage(),
+    };
+    let address: Address = match args.get(1).map(|x| x.parse()) {
+        Some(Ok(address)) => address,
+        _ => usage(),
+    };
+    let chain_id = match args.get(2).map(|x| x.parse()) {
+        Some(Ok(chain_id)) => chain_id,
+        Some(Err(_)) => usage(),
+        None => MAINNET_CHAIN_ID,
+    };
+    let runtime = Runtime::new().unwrap();
+    let db = runtime
+        .block_on(MongoRep::init(
+            dotenv::var("MONGO_URI").expect("MONGO_URI must be set"),
+            "lfb",
+        ))
+        .map(Arc::new)
+        .unwrap();
+    match runtime.block_on(db.set_recipe_status(chain_id, &address, status)) {
+        Ok(true) => println!("recipe {} is now {:?}", address, status),
+        Ok(false) => println!("recipe {} is already {:?}", address, status),
+        Err(e) => {
+            eprintln!("could not update recipe {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn usage<T>() -> T {
+    eprintln!("usage: recipe_status <pause|resume|cancel> <address> [chain_id]");
+    process::exit(1);
+}
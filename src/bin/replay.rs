@@ -0,0 +1,54 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+use lfb_back::*;
+use rocket::tokio::runtime::Runtime;
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let dry_run = args.iter().any(|x| x == "--dry-run");
+    args.retain(|x| x != "--dry-run");
+    let chain_id = match args.get(1).map(|x| x.parse()) {
+        None => Ok(MAINNET_CHAIN_ID),
+        Some(chain_id) => chain_id,
+    };
+    let (file, chain_id) = match (args.first(), chain_id) {
+        (Some(file), Ok(chain_id)) if args.len() <= 2 => (file, chain_id),
+        _ => {
+            eprintln!("usage: replay <logs.ndjson> [chain_id] [--dry-run]");
+            process::exit(1);
+        }
+    };
+    let logs = match read_logs(Path::new(file)) {
+        Ok(logs) => logs,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let runtime = Runtime::new().unwrap();
+    let db = runtime
+        .block_on(MongoRep::init(
+            dotenv::var("MONGO_URI").expect("MONGO_URI must be set"),
+            "lfb",
+        ))
+        .map(Arc::new)
+        .unwrap();
+    let mut indexer = Indexer::with_chain_id(
+        db,
+        runtime.handle().clone(),
+        IndexerConfig::offline(),
+        chain_id,
+    )
+    .unwrap()
+    .with_dry_run(dry_run);
+    match indexer.replay(logs) {
+        Ok(applied) => println!("applied {} logs of {} on chain {}", applied, file, chain_id),
+        Err(e) => {
+            eprintln!("could not replay logs: {}", e);
+            process::exit(1);
+        }
+    }
+}
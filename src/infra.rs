@@ -1,3 +1,15 @@
+mod ens;
+pub use ens::*;
+
+mod eth;
+pub use eth::*;
+
+mod indexer;
+pub use indexer::*;
+
+mod merkle;
+pub use merkle::*;
+
 mod mongo;
 pub use mongo::*;
 
@@ -0,0 +1,93 @@
+use super::merkle::{get_namehash, SIZE};
+use ens_normalize_rs::{EnsNameNormalizer, ProcessError};
+use std::sync::OnceLock;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum NormalizeError {
+    #[error("empty domain")]
+    EmptyName(),
+    #[error("domain {0} mixes confusable characters")]
+    ConfusableName(String),
+    #[error("domain {0} has an invalid character '{1}' at position {2}")]
+    InvalidCharacter(String, String, usize),
+    #[error("domain {0} has a disallowed sequence: {1}")]
+    DisallowedSequence(String, String),
+}
+
+impl NormalizeError {
+    fn from_process(domain: &str, error: ProcessError) -> Self {
+        let domain = domain.to_string();
+        match error {
+            ProcessError::Confused(_) | ProcessError::ConfusedGroups { .. } => {
+                NormalizeError::ConfusableName(domain)
+            }
+            ProcessError::CurrableError {
+                index, sequence, ..
+            } => NormalizeError::InvalidCharacter(domain, sequence, index),
+            ProcessError::DisallowedSequence(e) => {
+                NormalizeError::DisallowedSequence(domain, e.to_string())
+            }
+        }
+    }
+}
+
+// building the normalizer parses the ENSIP-15 spec, so it is only done once
+fn normalizer() -> &'static EnsNameNormalizer {
+    static NORMALIZER: OnceLock<EnsNameNormalizer> = OnceLock::new();
+    NORMALIZER.get_or_init(EnsNameNormalizer::default)
+}
+
+/// Normalizes `domain` following ENSIP-15 (UTS-46 mapping, NFC and ENS
+/// validation rules), so that it hashes to the same node as on-chain.
+pub fn normalize_domain(domain: &str) -> Result<String, NormalizeError> {
+    if domain.is_empty() {
+        return Err(NormalizeError::EmptyName());
+    }
+    normalizer()
+        .normalize(domain)
+        .map_err(|e| NormalizeError::from_process(domain, e))
+}
+
+pub fn get_normalized_namehash(domain: &str) -> Result<[u8; SIZE], NormalizeError> {
+    Ok(get_namehash(normalize_domain(domain)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_domain_lowercase() {
+        assert_eq!("abricot.eth", normalize_domain("Abricot.ETH").unwrap());
+        assert_eq!(
+            get_namehash(String::from("alice.eth")),
+            get_normalized_namehash("ALICE.eth").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_normalize_domain_unicode() {
+        assert_eq!("crème.eth", normalize_domain("Crème.eth").unwrap());
+        // decomposed "a" followed by a combining circumflex
+        assert_eq!("pâte.eth", normalize_domain("pa\u{302}te.eth").unwrap());
+        assert_eq!("🍓.eth", normalize_domain("🍓.eth").unwrap());
+    }
+
+    #[test]
+    fn test_normalize_domain_invalid() {
+        assert_eq!(Err(NormalizeError::EmptyName()), normalize_domain(""));
+        assert!(matches!(
+            normalize_domain("abricot..eth"),
+            Err(NormalizeError::DisallowedSequence(..))
+        ));
+        assert!(matches!(
+            normalize_domain("a_bricot.eth"),
+            Err(NormalizeError::InvalidCharacter(..))
+        ));
+        assert!(matches!(
+            normalize_domain("abricot!.eth"),
+            Err(NormalizeError::DisallowedSequence(..))
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+use super::merkle::{keccak256, SIZE};
+use mongodb::bson::Bson;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum HexError {
+    #[error("{0} is not 0x prefixed hex")]
+    InvalidHex(String),
+    #[error("{0} should be {1} bytes long")]
+    InvalidLength(String, usize),
+    #[error("{0} has an invalid checksum")]
+    InvalidChecksum(String),
+}
+
+// parses 0x prefixed hex of exactly N bytes
+fn parse_bytes<const N: usize>(value: &str) -> Result<[u8; N], HexError> {
+    let digits = value
+        .strip_prefix("0x")
+        .ok_or_else(|| HexError::InvalidHex(value.to_string()))?;
+    if digits.len() != 2 * N {
+        return Err(HexError::InvalidLength(value.to_string(), N));
+    }
+    let mut bytes = [0u8; N];
+    hex::decode_to_slice(digits, &mut bytes)
+        .map_err(|_| HexError::InvalidHex(value.to_string()))?;
+    Ok(bytes)
+}
+
+/// A 32 bytes hash, written as lowercase 0x prefixed hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct H256(pub [u8; SIZE]);
+
+impl H256 {
+    pub fn as_bytes(&self) -> &[u8; SIZE] {
+        &self.0
+    }
+}
+
+impl From<[u8; SIZE]> for H256 {
+    fn from(bytes: [u8; SIZE]) -> Self {
+        H256(bytes)
+    }
+}
+
+impl FromStr for H256 {
+    type Err = HexError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_bytes(value).map(H256)
+    }
+}
+
+impl fmt::Display for H256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+/// A 20 bytes account address, written with its EIP-55 checksum and read
+/// either checksummed or in a single case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Address(pub [u8; 20]);
+
+impl Address {
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(Address)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0u8; 20]
+    }
+
+    /// Address in lowercase hex, as found in the logs.
+    pub fn to_lowercase(&self) -> String {
+        format!("0x{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for Address {
+    type Err = HexError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let address = parse_bytes(value).map(Address)?;
+        let digits = &value[2..];
+        // mixed case addresses must carry a valid checksum
+        let mixed = digits.chars().any(|x| x.is_ascii_lowercase())
+            && digits.chars().any(|x| x.is_ascii_uppercase());
+        if mixed && address.to_string() != value {
+            return Err(HexError::InvalidChecksum(value.to_string()));
+        }
+        Ok(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = hex::encode(self.0);
+        let hash = keccak256(digits.as_bytes());
+        let checksummed: String = digits
+            .chars()
+            .enumerate()
+            .map(|(i, x)| {
+                let nibble = hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+                if nibble >= 8 {
+                    x.to_ascii_uppercase()
+                } else {
+                    x
+                }
+            })
+            .collect();
+        write!(f, "0x{}", checksummed)
+    }
+}
+
+macro_rules! hex_serde {
+    ($name:ident) => {
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                value.parse().map_err(de::Error::custom)
+            }
+        }
+
+        impl From<$name> for Bson {
+            fn from(value: $name) -> Self {
+                Bson::String(value.to_string())
+            }
+        }
+    };
+}
+
+hex_serde!(H256);
+hex_serde!(Address);
+
+/// Reads an optional value written as an empty string when missing, as in
+/// the documents stored before the values were typed.
+pub fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) if !value.is_empty() => value.parse().map(Some).map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_checksum() {
+        // test vectors of EIP-55
+        for checksummed in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let address: Address = checksummed.parse().unwrap();
+            assert_eq!(address.to_string(), checksummed);
+            assert_eq!(
+                checksummed.to_lowercase().parse::<Address>().unwrap(),
+                address
+            );
+            assert_eq!(
+                checksummed
+                    .to_uppercase()
+                    .replace("0X", "0x")
+                    .parse::<Address>()
+                    .unwrap(),
+                address
+            );
+        }
+        assert_eq!(
+            "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse::<Address>(),
+            Err(HexError::InvalidChecksum(String::from(
+                "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+            )))
+        );
+        assert!(matches!(
+            "0x5aaeb6".parse::<Address>(),
+            Err(HexError::InvalidLength(_, 20))
+        ));
+        assert!(matches!(
+            "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse::<Address>(),
+            Err(HexError::InvalidHex(_))
+        ));
+    }
+
+    #[test]
+    fn test_serde() {
+        let address: Address =
+            serde_json::from_str("\"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed\"").unwrap();
+        assert_eq!(
+            serde_json::to_string(&address).unwrap(),
+            "\"0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed\""
+        );
+        let hash = "\"0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e\"";
+        let value: H256 = serde_json::from_str(&hash.to_uppercase().replace("0X", "0x")).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(), hash);
+        assert!(serde_json::from_str::<H256>("\"0x1234\"").is_err());
+    }
+
+    #[test]
+    fn test_empty_as_none() {
+        #[derive(Deserialize)]
+        struct Owner {
+            #[serde(default, deserialize_with = "empty_as_none")]
+            owner: Option<Address>,
+        }
+        let owner = |json: &str| serde_json::from_str::<Owner>(json).map(|x| x.owner);
+        assert_eq!(owner("{}").unwrap(), None);
+        assert_eq!(owner("{\"owner\": \"\"}").unwrap(), None);
+        assert_eq!(owner("{\"owner\": null}").unwrap(), None);
+        assert!(
+            owner("{\"owner\": \"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed\"}")
+                .unwrap()
+                .is_some()
+        );
+        assert!(owner("{\"owner\": \"0x12\"}").is_err());
+    }
+}
@@ -0,0 +1,1424 @@
+mod abi;
+pub use abi::*;
+mod ens;
+pub use ens::*;
+mod events;
+pub use events::*;
+mod mutation;
+pub use mutation::*;
+mod replay;
+pub use replay::*;
+mod rpc;
+pub use rpc::*;
+
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::TreeHashing;
+use crate::infra::mongo::{
+    Block, BlockChange, Completion, HistoryEvent, HistoryEventKind, MongoRepError, Repository,
+};
+use rocket::tokio::runtime::Handle;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IndexerError {
+    #[error("rpc request failed: {0}")]
+    RpcError(String),
+    #[error("invalid rpc response: {0}")]
+    InvalidResponse(String),
+    #[error("could not decode log {0}")]
+    InvalidLog(String),
+    #[error("invalid contract abi: {0}")]
+    InvalidAbi(String),
+    #[error("invalid log file: {0}")]
+    InvalidLogFile(String),
+    #[error("could not apply event")]
+    StorageError(#[from] MongoRepError),
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexerConfig {
+    pub rpc_url: String,
+    // addresses of the factories deploying the recipe contracts, for the
+    // contract versions whose ABI does not name one
+    pub factories: Vec<String>,
+    // directory of the ABI of each contract version
+    pub abi_dir: PathBuf,
+    // address of the .eth registrar, its logs give the owner of the ingredients
+    pub ens_registrar: Option<String>,
+    // first block to index when the database holds no recipe
+    pub start_block: u64,
+    // maximum number of blocks requested in a single eth_getLogs call
+    pub batch_size: u64,
+    // number of blocks after which a block is considered final
+    pub reorg_depth: u64,
+    // number of blocks, including its own, before a found ingredient is completed
+    pub confirmations: u64,
+    pub poll_interval: Duration,
+    // hashing of the recipe trees, the one the recipe contracts verify with
+    pub hashing: TreeHashing,
+}
+
+impl IndexerConfig {
+    /// Reads the configuration from the environment, `None` when no RPC_URL is set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_env_with_prefix("")
+    }
+
+    /// Reads the configuration of every indexed chain, the chains listed in
+    /// CHAINS read their variables prefixed by their name, e.g. `OPTIMISM_RPC_URL`.
+    pub fn all_from_env() -> Vec<Self> {
+        match dotenv::var("CHAINS") {
+            Ok(chains) => chains
+                .split(',')
+                .filter_map(|x| {
+                    let config = Self::from_chain(x);
+                    if config.is_none() {
+                        println!("no {}_RPC_URL set, chain {} not indexed", x, x);
+                    }
+                    config
+                })
+                .collect(),
+            Err(_) => Self::from_env().into_iter().collect(),
+        }
+    }
+
+    /// Reads the configuration of the chain `name` listed in CHAINS.
+    pub fn from_chain(name: &str) -> Option<Self> {
+        Self::from_env_with_prefix(&format!("{}_", name.trim().to_uppercase()))
+    }
+
+    /// Reads the configuration from the environment without requiring an
+    /// RPC_URL, to replay recorded logs.
+    pub fn offline() -> Self {
+        Self::read("", dotenv::var("RPC_URL").unwrap_or_default())
+    }
+
+    fn from_env_with_prefix(prefix: &str) -> Option<Self> {
+        let rpc_url = dotenv::var(format!("{}RPC_URL", prefix)).ok()?;
+        Some(Self::read(prefix, rpc_url))
+    }
+
+    // the rpc and the factories are specific to the chain, the other variables
+    // fall back to their unprefixed value
+    fn read(prefix: &str, rpc_url: String) -> Self {
+        let var = |name: &str| dotenv::var(format!("{}{}", prefix, name));
+        let factories = var("RECIPE_FACTORY_ADDRESS")
+            .unwrap_or_else(|_| panic!("{}RECIPE_FACTORY_ADDRESS must be set", prefix))
+            .split(',')
+            .map(|x| x.trim().to_lowercase())
+            .collect();
+        let number = |name: &str, default: u64| {
+            var(name)
+                .or_else(|_| dotenv::var(name))
+                .map(|x| {
+                    x.parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number", name))
+                })
+                .unwrap_or(default)
+        };
+        IndexerConfig {
+            rpc_url,
+            factories,
+            abi_dir: var("INDEXER_ABI_DIR")
+                .or_else(|_| dotenv::var("INDEXER_ABI_DIR"))
+                .unwrap_or_else(|_| String::from("abi"))
+                .into(),
+            ens_registrar: var("ENS_REGISTRAR_ADDRESS").ok(),
+            start_block: number("INDEXER_START_BLOCK", 0),
+            batch_size: number("INDEXER_BATCH_SIZE", 1000).max(1),
+            reorg_depth: number("INDEXER_REORG_DEPTH", 64),
+            confirmations: number("INDEXER_CONFIRMATIONS", 12),
+            poll_interval: Duration::from_secs(number("INDEXER_POLL_SECS", 12)),
+            hashing: TreeHashing::sorted(
+                var("RECIPE_HASH_FUNCTION")
+                    .or_else(|_| dotenv::var("RECIPE_HASH_FUNCTION"))
+                    .map(|x| x.parse().unwrap_or_else(|e| panic!("{}", e)))
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+/// Polls the chain for recipe logs and applies them to the database.
+pub struct Indexer {
+    rpc: RpcClient,
+    // chain reported by the rpc, every record of the indexer belongs to it
+    chain_id: i64,
+    abis: ContractAbis,
+    registrar: Option<EnsRegistrar>,
+    db: Arc<dyn Repository>,
+    // runtime of the database client, the indexer blocks on it from its own thread
+    runtime: Handle,
+    config: IndexerConfig,
+    next_block: u64,
+    // prints the mutations instead of applying them
+    dry_run: bool,
+    // recipes created during a dry run, they are not in the database
+    dry_run_recipes: HashSet<Address>,
+}
+
+impl Indexer {
+    pub fn new(
+        db: Arc<dyn Repository>,
+        runtime: Handle,
+        config: IndexerConfig,
+    ) -> Result<Self, IndexerError> {
+        let chain_id = to_i64(RpcClient::new(config.rpc_url.clone()).chain_id()?)?;
+        Self::with_chain_id(db, runtime, config, chain_id)
+    }
+
+    /// Indexer of the chain `chain_id`, the rpc is not queried for it so that
+    /// recorded logs can be replayed offline.
+    pub fn with_chain_id(
+        db: Arc<dyn Repository>,
+        runtime: Handle,
+        config: IndexerConfig,
+        chain_id: i64,
+    ) -> Result<Self, IndexerError> {
+        db.set_recipe_hashing(chain_id, config.hashing);
+        let next_block = match runtime.block_on(db.get_latest_block(chain_id))? {
+            Some(block) => block.number + 1,
+            // the last block may only be partially indexed, applying it again is harmless
+            None => runtime.block_on(db.get_last_block(chain_id))?,
+        };
+        Ok(Indexer {
+            rpc: RpcClient::new(config.rpc_url.clone()),
+            chain_id,
+            abis: ContractAbis::load(&config.abi_dir)?,
+            registrar: config.ens_registrar.as_deref().map(EnsRegistrar::new),
+            next_block: u64::try_from(next_block)
+                .unwrap_or_default()
+                .max(config.start_block),
+            db,
+            runtime,
+            config,
+            dry_run: false,
+            dry_run_recipes: HashSet::new(),
+        })
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn chain_id(&self) -> i64 {
+        self.chain_id
+    }
+
+    pub fn next_block(&self) -> u64 {
+        self.next_block
+    }
+
+    /// Indexes every block up to the head of the chain, returns the head.
+    pub fn poll(&mut self) -> Result<u64, IndexerError> {
+        self.handle_reorg()?;
+        let head = self.rpc.block_number()?;
+        while self.next_block <= head {
+            let to = head.min(self.next_block + self.config.batch_size - 1);
+            let mut blocks = self.index_range(self.next_block, to)?;
+            // the last block of the range is the one the next range must extend
+            blocks.entry(to).or_default();
+            for (number, changes) in blocks {
+                self.record_block(number, changes)?;
+            }
+            self.next_block = to + 1;
+        }
+        self.confirm_ingredients(head)?;
+        let finalized = head.saturating_sub(self.config.reorg_depth);
+        self.execute(Mutation::PruneBlocks {
+            chain_id: self.chain_id,
+            number: to_i64(finalized)?,
+        })?;
+        Ok(head)
+    }
+
+    /// Scans the blocks `from..=to` again and applies their logs, the recipes
+    /// already in the database are kept as is. Returns the number of applied logs.
+    pub fn backfill(&mut self, from: u64, to: u64) -> Result<usize, IndexerError> {
+        let head = self.rpc.block_number()?;
+        let to = to.min(head);
+        let mut applied = 0;
+        let mut start = from;
+        while start <= to {
+            let end = to.min(start + self.config.batch_size - 1);
+            applied += self
+                .index_range(start, end)?
+                .values()
+                .map(Vec::len)
+                .sum::<usize>();
+            println!("backfilled blocks {} to {}", start, end);
+            start = end + 1;
+        }
+        self.confirm_ingredients(head)?;
+        Ok(applied)
+    }
+
+    /// Applies recorded logs instead of the ones of the rpc, the last block of
+    /// the logs is taken as the head of the chain. Returns the number of applied logs.
+    pub fn replay(&mut self, logs: Vec<Log>) -> Result<usize, IndexerError> {
+        let head = logs
+            .iter()
+            .map(|x| x.block_number())
+            .collect::<Result<Vec<u64>, IndexerError>>()?
+            .into_iter()
+            .max();
+        let applied = self.apply_logs(logs)?.values().map(Vec::len).sum::<usize>();
+        if let Some(head) = head {
+            self.confirm_ingredients(head)?;
+        }
+        Ok(applied)
+    }
+
+    /// Applies the recipe and registrar logs of the blocks `from..=to`, returns
+    /// the changes made by each block. Only the logs of the factories, of the
+    /// known recipes and of the recipes created in the range are requested.
+    fn index_range(
+        &mut self,
+        from: u64,
+        to: u64,
+    ) -> Result<BTreeMap<u64, Vec<BlockChange>>, IndexerError> {
+        let factories = self.abis.factories(&self.config.factories);
+        let mut logs = self
+            .rpc
+            .get_logs(&factories, from, to, &self.abis.factory_topics())?;
+        let mut recipes: BTreeSet<String> = self
+            .runtime
+            .block_on(self.db.get_recipe_addresses(self.chain_id))?
+            .iter()
+            .map(|x| x.to_lowercase())
+            .chain(self.dry_run_recipes.iter().map(|x| x.to_lowercase()))
+            .collect();
+        for log in logs.iter() {
+            if let Some(RecipeEvent::RecipeCreated { recipe, .. }) =
+                self.abis.decode_log(log, &self.config.factories)?
+            {
+                recipes.insert(recipe.to_lowercase());
+            }
+        }
+        let recipes: Vec<String> = recipes.into_iter().collect();
+        logs.extend(
+            self.rpc
+                .get_logs(&recipes, from, to, &self.abis.recipe_topics())?,
+        );
+        if let Some(registrar) = &self.registrar {
+            // the registrar events are common to every token contract
+            logs.extend(self.rpc.get_contract_logs(
+                registrar.address(),
+                from,
+                to,
+                &registrar.topics(),
+            )?);
+        }
+        self.apply_logs(logs)
+    }
+
+    fn apply_logs(
+        &mut self,
+        mut logs: Vec<Log>,
+    ) -> Result<BTreeMap<u64, Vec<BlockChange>>, IndexerError> {
+        logs.sort_by_key(|x| (x.block_number().ok(), x.log_index().ok()));
+        let mut blocks: BTreeMap<u64, Vec<BlockChange>> = BTreeMap::new();
+        for log in logs.iter().filter(|x| !x.removed) {
+            let ens_event = match &self.registrar {
+                Some(registrar) => registrar.decode_log(log)?,
+                None => None,
+            };
+            let change = match ens_event {
+                Some(event) => self.apply_ens(&event)?,
+                None => match self.abis.decode_log(log, &self.config.factories)? {
+                    Some(event) => self.apply(log, &event)?,
+                    None => None,
+                },
+            };
+            if let Some(change) = change {
+                blocks.entry(log.block_number()?).or_default().push(change);
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn confirm_ingredients(&mut self, head: u64) -> Result<(), IndexerError> {
+        self.execute(Mutation::ConfirmIngredients {
+            chain_id: self.chain_id,
+            head: to_i64(head)?,
+            confirmations: to_i64(self.config.confirmations)?,
+        })?;
+        Ok(())
+    }
+
+    fn record_block(&mut self, number: u64, changes: Vec<BlockChange>) -> Result<(), IndexerError> {
+        let header = self
+            .rpc
+            .get_block(number)?
+            .ok_or_else(|| IndexerError::InvalidResponse(format!("missing block {}", number)))?;
+        let timestamp = match &header.timestamp {
+            Some(timestamp) => to_i64(parse_quantity(timestamp)?)?,
+            None => 0,
+        };
+        self.execute(Mutation::AddBlock(Block {
+            chain_id: self.chain_id,
+            number: to_i64(number)?,
+            hash: to_h256(&header.hash)?,
+            parent_hash: to_h256(&header.parent_hash)?,
+            timestamp,
+            changes,
+        }))?;
+        Ok(())
+    }
+
+    /// Rolls back the indexed blocks which are no longer part of the canonical
+    /// chain, returns the number of blocks rolled back.
+    pub fn handle_reorg(&mut self) -> Result<usize, IndexerError> {
+        let tip = match self
+            .runtime
+            .block_on(self.db.get_latest_block(self.chain_id))?
+        {
+            Some(tip) => tip,
+            None => return Ok(0),
+        };
+        let number = u64::try_from(tip.number).unwrap_or_default();
+        let canonical = match self.rpc.get_block(number + 1)? {
+            Some(next) => to_h256(&next.parent_hash)? == tip.hash,
+            None => self.is_canonical(&tip)?,
+        };
+        if canonical {
+            return Ok(0);
+        }
+
+        let mut rolled_back = 0;
+        let blocks = self
+            .runtime
+            .block_on(self.db.get_blocks_since(self.chain_id, 0))?;
+        // only the blocks with changes are recorded, the fork may start anywhere
+        // after the last canonical one
+        self.next_block = self.config.start_block;
+        for block in blocks {
+            if self.is_canonical(&block)? {
+                self.next_block = u64::try_from(block.number + 1).unwrap_or_default();
+                break;
+            }
+            self.execute(Mutation::RollbackBlock(block))?;
+            rolled_back += 1;
+        }
+        println!(
+            "chain reorganization, rolled back {} blocks, indexing again from block {}",
+            rolled_back, self.next_block
+        );
+        Ok(rolled_back)
+    }
+
+    fn is_canonical(&self, block: &Block) -> Result<bool, IndexerError> {
+        let number = u64::try_from(block.number).unwrap_or_default();
+        match self.rpc.get_block(number)? {
+            Some(header) => Ok(to_h256(&header.hash)? == block.hash),
+            None => Ok(false),
+        }
+    }
+
+    /// Applies the event decoded from `log` and appends it to the recipe
+    /// history. Ingredients of unknown recipes are skipped.
+    pub fn apply(
+        &mut self,
+        log: &Log,
+        event: &RecipeEvent,
+    ) -> Result<Option<BlockChange>, IndexerError> {
+        let block = to_i64(log.block_number()?)?;
+        let (address, kind, change) = match event {
+            RecipeEvent::RecipeCreated {
+                recipe,
+                ingredients,
+                version,
+                deadline,
+            } => {
+                let applied = self.execute(Mutation::AddRecipe {
+                    chain_id: self.chain_id,
+                    address: *recipe,
+                    ingredients: ingredients.clone(),
+                    block,
+                    version: version.clone(),
+                    deadline: *deadline,
+                })?;
+                // a recipe naming ingredients missing from the catalog is left
+                // out, its completions are then skipped as for any unknown recipe
+                if !applied {
+                    return Ok(None);
+                }
+                (
+                    recipe,
+                    HistoryEventKind::RecipeCreated {
+                        ingredients: ingredients.clone(),
+                        version: version.clone(),
+                        deadline: *deadline,
+                    },
+                    BlockChange::RecipeCreated { address: *recipe },
+                )
+            }
+            RecipeEvent::IngredientCompleted {
+                recipe,
+                ingredient,
+                owner,
+            } => {
+                if !self.has_recipe(recipe)? {
+                    return Ok(None);
+                }
+                let timestamp = self.block_timestamp(log)?;
+                let applied = self.execute(Mutation::UpdateRecipe {
+                    chain_id: self.chain_id,
+                    address: *recipe,
+                    ingredient: *ingredient,
+                    completion: Completion {
+                        owner: *owner,
+                        block,
+                        timestamp,
+                        transaction_hash: to_h256(&log.transaction_hash)?,
+                    },
+                })?;
+                // a rejected completion is left out of the history, and of the
+                // block as rolling it back would undo the completion it was
+                // rejected for
+                if !applied {
+                    return Ok(None);
+                }
+                (
+                    recipe,
+                    HistoryEventKind::IngredientCompleted {
+                        ingredient: *ingredient,
+                        owner: *owner,
+                        timestamp,
+                    },
+                    BlockChange::IngredientCompleted {
+                        address: *recipe,
+                        hash: *ingredient,
+                    },
+                )
+            }
+        };
+        self.execute(Mutation::AddHistoryEvent(HistoryEvent {
+            chain_id: self.chain_id,
+            address: *address,
+            block,
+            transaction_hash: to_h256(&log.transaction_hash)?,
+            log_index: to_i64(log.log_index()?)?,
+            kind,
+        }))?;
+        Ok(Some(change))
+    }
+
+    /// Applies an event of the registrar to the ingredient it names, the other
+    /// names are skipped.
+    pub fn apply_ens(&mut self, event: &EnsEvent) -> Result<Option<BlockChange>, IndexerError> {
+        let (label_hash, owner, expiry) = match event {
+            EnsEvent::Transfer { label_hash, owner } => (label_hash, Some(owner), None),
+            EnsEvent::NameRegistered {
+                label_hash,
+                owner,
+                expires,
+            } => (label_hash, Some(owner), Some(*expires)),
+            EnsEvent::NameRenewed {
+                label_hash,
+                expires,
+            } => (label_hash, None, Some(*expires)),
+        };
+        let hash = eth_namehash(label_hash);
+        let ingredients = self
+            .runtime
+            .block_on(self.db.get_ingredients_by_hash(&[hash]))?;
+        let ingredient = match ingredients.into_iter().next() {
+            Some(ingredient) => ingredient,
+            None => return Ok(None),
+        };
+        self.execute(Mutation::UpdateIngredientOwner {
+            hash,
+            owner: owner.copied(),
+            expiry,
+        })?;
+        Ok(Some(BlockChange::IngredientTransferred {
+            hash,
+            owner: ingredient.owner,
+            expiry: ingredient.expiry,
+        }))
+    }
+
+    // timestamp of the block of `log`, 0 when it can not be read offline
+    fn block_timestamp(&self, log: &Log) -> Result<i64, IndexerError> {
+        if let Some(timestamp) = log.block_timestamp()? {
+            return to_i64(timestamp);
+        }
+        if self.config.rpc_url.is_empty() {
+            return Ok(0);
+        }
+        match self
+            .rpc
+            .get_block(log.block_number()?)?
+            .and_then(|x| x.timestamp)
+        {
+            Some(timestamp) => to_i64(parse_quantity(&timestamp)?),
+            None => Ok(0),
+        }
+    }
+
+    fn has_recipe(&self, address: &Address) -> Result<bool, IndexerError> {
+        if self.dry_run && self.dry_run_recipes.contains(address) {
+            return Ok(true);
+        }
+        match self
+            .runtime
+            .block_on(self.db.get_recipe(self.chain_id, address))
+        {
+            Ok(_) => Ok(true),
+            Err(MongoRepError::EmptyResponse()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // every write of the indexer goes through here so that a dry run can print it
+    // returns whether the mutation was applied, a dry run applies them all
+    fn execute(&mut self, mutation: Mutation) -> Result<bool, IndexerError> {
+        if !self.dry_run {
+            let applied = self.runtime.block_on(mutation.apply(self.db.as_ref()))?;
+            if !applied {
+                println!("skipped {}", mutation);
+            }
+            return Ok(applied);
+        }
+        if let Mutation::AddRecipe { address, .. } = &mutation {
+            self.dry_run_recipes.insert(*address);
+        }
+        println!("{}", mutation);
+        Ok(true)
+    }
+
+    pub fn run(mut self) {
+        loop {
+            if let Err(e) = self.poll() {
+                println!("could not index recipes of chain {}: {}", self.chain_id, e);
+            }
+            thread::sleep(self.config.poll_interval);
+        }
+    }
+}
+
+fn to_i64(number: u64) -> Result<i64, IndexerError> {
+    i64::try_from(number).map_err(|_| IndexerError::InvalidResponse(number.to_string()))
+}
+
+fn to_h256(hash: &str) -> Result<H256, IndexerError> {
+    hash.parse()
+        .map_err(|_| IndexerError::InvalidResponse(hash.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ens::tests::{name_registered_log, transfer_log, REGISTRAR};
+    use super::events::tests::{ingredient_completed_log, recipe_created_log, FACTORY};
+    use super::*;
+    use crate::infra::merkle::get_namehash;
+    use crate::infra::mongo::{MemoryRep, Status};
+    use rocket::tokio::runtime::Runtime;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    const RECIPE: &str = "0x00000000000000000000000000000000000012aa";
+    const OWNER: &str = "0xc5e4ec0073631fa872334749381e4d514da130f8";
+
+    fn address(address: &str) -> Address {
+        address.parse().unwrap()
+    }
+
+    // a chain whose blocks from `fork.0` onwards carry the hashes of branch `fork.1`
+    #[derive(Default)]
+    struct Chain {
+        id: u64,
+        head: u64,
+        fork: (u64, u8),
+        logs: Vec<Log>,
+        // filters of the eth_getLogs requests served
+        filters: Vec<Value>,
+    }
+
+    impl Chain {
+        fn hash(&self, number: u64) -> String {
+            let branch = if number >= self.fork.0 {
+                self.fork.1
+            } else {
+                0
+            };
+            format!("0x{:02x}{:062x}", branch, number)
+        }
+    }
+
+    fn serve_chain(chain: Arc<Mutex<Chain>>) -> String {
+        mock::serve(move |method, params| {
+            let mut chain = chain.lock().unwrap();
+            match method {
+                "eth_chainId" => Ok(json!(to_quantity(chain.id))),
+                "eth_blockNumber" => Ok(json!(to_quantity(chain.head))),
+                "eth_getBlockByNumber" => {
+                    let number = parse_quantity(params[0].as_str().unwrap()).unwrap();
+                    if number > chain.head {
+                        return Ok(Value::Null);
+                    }
+                    Ok(json!({
+                        "number": to_quantity(number),
+                        "hash": chain.hash(number),
+                        "parentHash": chain.hash(number.saturating_sub(1)),
+                        "timestamp": to_quantity(number * 12),
+                    }))
+                }
+                "eth_getLogs" => {
+                    let from = parse_quantity(params[0]["fromBlock"].as_str().unwrap()).unwrap();
+                    let to = parse_quantity(params[0]["toBlock"].as_str().unwrap()).unwrap();
+                    let filter = &params[0];
+                    chain.filters.push(filter.clone());
+                    let logs: Vec<&Log> = chain
+                        .logs
+                        .iter()
+                        .filter(|x| (from..=to).contains(&x.block_number().unwrap()))
+                        .filter(|x| {
+                            filter["topics"][0]
+                                .as_array()
+                                .unwrap()
+                                .contains(&json!(x.topics[0]))
+                        })
+                        .filter(|x| match &filter["address"] {
+                            Value::Array(addresses) => addresses
+                                .iter()
+                                .any(|a| a.as_str().unwrap().eq_ignore_ascii_case(&x.address)),
+                            address => address.as_str().unwrap().eq_ignore_ascii_case(&x.address),
+                        })
+                        .collect();
+                    Ok(json!(logs))
+                }
+                _ => Err(json!({"code": -32601, "message": "method not found"})),
+            }
+        })
+    }
+
+    fn config(rpc_url: String) -> IndexerConfig {
+        IndexerConfig {
+            rpc_url,
+            factories: vec![FACTORY.to_string()],
+            abi_dir: PathBuf::from("abi"),
+            ens_registrar: None,
+            start_block: 0,
+            batch_size: 2,
+            reorg_depth: 64,
+            confirmations: 1,
+            poll_interval: Duration::from_secs(1),
+            hashing: TreeHashing::default(),
+        }
+    }
+
+    // a database holding only the ingredients used by the logs
+    fn init_repo() -> Arc<MemoryRep> {
+        Arc::new(MemoryRep::with_ingredients(&["abricot.eth", "ail.eth"]))
+    }
+
+    // a recipe created at block 1 whose two ingredients are completed at blocks 2 and 3
+    fn index_completed_recipe() -> (Runtime, Arc<MemoryRep>) {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 3,
+            logs: vec![
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+                ingredient_completed_log(3, RECIPE, "ail.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain)),
+        )
+        .unwrap();
+        assert_eq!(indexer.poll().unwrap(), 3);
+        assert_eq!(indexer.next_block(), 4);
+        (runtime, db)
+    }
+
+    #[test]
+    fn test_indexer_applies_logs() {
+        let (runtime, db) = index_completed_recipe();
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.status, Status::Completed);
+        assert_eq!(recipe.last_block, 3);
+        assert_eq!(recipe.version, "v1");
+        assert_eq!(recipe.ingredients[0].owner, Some(address(OWNER)));
+        assert_eq!(recipe.ingredients[0].block, 2);
+        assert_eq!(recipe.ingredients[0].timestamp, 24);
+        assert_eq!(
+            recipe.ingredients[0].transaction_hash,
+            Some(
+                to_h256(
+                    &ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER).transaction_hash
+                )
+                .unwrap()
+            )
+        );
+        let head = runtime.block_on(db.get_latest_block(1)).unwrap().unwrap();
+        assert_eq!(head.number, 3);
+        assert_eq!(head.timestamp, 36);
+    }
+
+    #[test]
+    fn test_indexer_records_history() {
+        let (runtime, db) = index_completed_recipe();
+        let history = runtime
+            .block_on(db.get_recipe_history(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(
+            history[1].kind,
+            HistoryEventKind::IngredientCompleted {
+                ingredient: H256(get_namehash(String::from("abricot.eth"))),
+                owner: address(OWNER),
+                timestamp: 24,
+            }
+        );
+        assert_eq!(history[3].kind, HistoryEventKind::RecipeCompleted);
+        assert_eq!(history[3].block, 3);
+    }
+
+    #[test]
+    fn test_indexer_answers_at_past_blocks() {
+        let (runtime, db) = index_completed_recipe();
+        assert_eq!(
+            runtime.block_on(db.get_leaderboard_at(1, 1)).unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            runtime.block_on(db.get_leaderboard_at(1, 2)).unwrap(),
+            vec![(address(OWNER), 1)]
+        );
+        assert_eq!(
+            runtime
+                .block_on(db.get_statistics_at(1, &address(OWNER), 3))
+                .unwrap(),
+            vec![(1, 2)]
+        );
+        let recipes = runtime
+            .block_on(db.get_recipes_at(1, vec!["ail.eth", "abricot.eth"], 2))
+            .unwrap();
+        assert_eq!(recipes.len(), 1);
+        assert_eq!(recipes[0].status, Status::Ongoing);
+        assert!(runtime
+            .block_on(db.get_recipes_at(1, vec!["ail.eth", "abricot.eth"], 0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_indexer_finalizes_blocks_after_confirmations() {
+        let (runtime, db) = index_completed_recipe();
+        // the confirmations recorded by the indexer are read back, not its config
+        assert_eq!(runtime.block_on(db.get_confirmations(1)).unwrap(), Some(1));
+        assert_eq!(
+            runtime.block_on(db.get_finalized_block(1)).unwrap(),
+            Some(2)
+        );
+        assert!(runtime.block_on(db.check_finalized(1, 2)).is_ok());
+        assert!(matches!(
+            runtime.block_on(db.check_finalized(1, 3)),
+            Err(MongoRepError::BlockNotFinalized(3))
+        ));
+
+        // an indexer run with more confirmations
+        runtime.block_on(db.confirm_ingredients(1, 3, 3)).unwrap();
+        assert_eq!(
+            runtime.block_on(db.get_finalized_block(1)).unwrap(),
+            Some(0)
+        );
+        assert!(runtime.block_on(db.check_finalized(1, 1)).is_err());
+
+        // a chain without indexer has no finalized block
+        assert_eq!(runtime.block_on(db.get_finalized_block(5)).unwrap(), None);
+        assert!(runtime.block_on(db.check_finalized(5, 0)).is_err());
+    }
+
+    #[test]
+    fn test_indexer_history_rebuilds_recipes() {
+        let (runtime, db) = index_completed_recipe();
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        db.recipes.lock().unwrap().clear();
+        assert_eq!(runtime.block_on(db.rebuild_recipes()).unwrap(), 1);
+        let rebuilt = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(rebuilt.status, Status::Completed);
+        assert_eq!(rebuilt.root, recipe.root);
+        assert_eq!(rebuilt.ingredients[1].owner, Some(address(OWNER)));
+        assert_eq!(rebuilt.ingredients[1].timestamp, 36);
+    }
+
+    #[test]
+    fn test_indexer_filters_logs_by_address() {
+        let other = "0x00000000000000000000000000000000000099aa";
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 4,
+            logs: vec![
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, other, "abricot.eth", OWNER),
+                ingredient_completed_log(3, RECIPE, "abricot.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain.clone())),
+        )
+        .unwrap();
+        indexer.poll().unwrap();
+
+        let filters = chain.lock().unwrap().filters.clone();
+        // the factory, then the recipes of each batch of 2 blocks
+        assert_eq!(filters.len(), 6);
+        for filter in filters.iter() {
+            assert!(!filter["address"].as_array().unwrap().is_empty());
+            assert!(!filter["address"]
+                .as_array()
+                .unwrap()
+                .contains(&json!(other)));
+        }
+        assert_eq!(filters[0]["address"], json!([FACTORY]));
+        // the recipe created in the batch is followed right away
+        assert_eq!(filters[1]["address"], json!([RECIPE]));
+        assert_eq!(filters[3]["address"], json!([RECIPE]));
+        assert_eq!(filters[5]["address"], json!([RECIPE]));
+        assert_eq!(
+            runtime
+                .block_on(db.get_recipe_history(1, &address(RECIPE)))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_backfill_rebuilds_recipes() {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 10,
+            logs: vec![
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+                ingredient_completed_log(3, RECIPE, "ail.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain)),
+        )
+        .unwrap();
+        assert_eq!(indexer.backfill(0, 2).unwrap(), 2);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.status, Status::Ongoing);
+        assert_eq!(recipe.ingredients[0].status, Status::Completed);
+
+        // scanning the same blocks again leaves the recipe as is, the completion
+        // already applied is skipped
+        assert_eq!(indexer.backfill(0, 20).unwrap(), 2);
+        assert_eq!(indexer.backfill(0, 20).unwrap(), 1);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.status, Status::Completed);
+        assert!(recipe.root.is_some());
+        assert_eq!(db.recipes.lock().unwrap().len(), 1);
+        // backfilled blocks are not tracked for reorganizations
+        assert_eq!(runtime.block_on(db.get_latest_block(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_indexers_keep_chains_apart() {
+        let logs = vec![
+            recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+            ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+        ];
+        let mainnet = Arc::new(Mutex::new(Chain {
+            id: 1,
+            head: 2,
+            logs: logs.clone(),
+            ..Default::default()
+        }));
+        let optimism = Arc::new(Mutex::new(Chain {
+            id: 10,
+            head: 1,
+            logs,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut first = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(mainnet)),
+        )
+        .unwrap();
+        let mut second = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(optimism)),
+        )
+        .unwrap();
+        assert_eq!(second.chain_id(), 10);
+        first.poll().unwrap();
+        second.poll().unwrap();
+
+        // the same recipe address is a different recipe on each chain
+        let recipe = runtime
+            .block_on(db.get_recipe(10, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.chain_id, 10);
+        assert!(recipe
+            .ingredients
+            .iter()
+            .all(|x| x.status == Status::Ongoing));
+        assert_eq!(
+            runtime
+                .block_on(db.get_latest_block(1))
+                .unwrap()
+                .unwrap()
+                .number,
+            2
+        );
+        assert_eq!(
+            runtime
+                .block_on(db.get_latest_block(10))
+                .unwrap()
+                .unwrap()
+                .number,
+            1
+        );
+        assert_eq!(
+            runtime.block_on(db.get_leaderboard(Some(1))).unwrap(),
+            vec![(address(OWNER), 1)]
+        );
+        assert_eq!(
+            runtime.block_on(db.get_leaderboard(Some(10))).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_replay_dry_run_does_not_write() {
+        let logs = vec![
+            ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+            recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+            // an ingredient of a recipe which was never created
+            ingredient_completed_log(2, OWNER, "ail.eth", OWNER),
+        ];
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        // no rpc is needed to replay recorded logs
+        let mut indexer = Indexer::with_chain_id(
+            db.clone(),
+            runtime.handle().clone(),
+            config(String::new()),
+            1,
+        )
+        .unwrap()
+        .with_dry_run(true);
+        assert_eq!(indexer.replay(logs.clone()).unwrap(), 2);
+        assert!(matches!(
+            runtime.block_on(db.get_recipe(1, &address(RECIPE))),
+            Err(MongoRepError::EmptyResponse())
+        ));
+        assert_eq!(db.recipe_events.lock().unwrap().len(), 0);
+
+        let mut indexer = Indexer::with_chain_id(
+            db.clone(),
+            runtime.handle().clone(),
+            config(String::new()),
+            1,
+        )
+        .unwrap();
+        assert_eq!(indexer.replay(logs).unwrap(), 2);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.ingredients[0].status, Status::Completed);
+        assert_eq!(recipe.ingredients[1].status, Status::Ongoing);
+    }
+
+    #[test]
+    fn test_indexer_tracks_ingredient_owners() {
+        let other = "0x0000000000000000000000000000000000000bbb";
+        let chain = Arc::new(Mutex::new(Chain {
+            id: 1,
+            head: 2,
+            logs: vec![
+                transfer_log(
+                    1,
+                    "abricot.eth",
+                    "0x0000000000000000000000000000000000000000",
+                    OWNER,
+                ),
+                name_registered_log(1, "abricot.eth", OWNER, 4_000_000_000),
+                // not an ingredient
+                name_registered_log(1, "banane.eth", OWNER, 4_000_000_000),
+                transfer_log(2, "abricot.eth", OWNER, other),
+            ],
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut config = config(serve_chain(chain.clone()));
+        config.ens_registrar = Some(REGISTRAR.to_string());
+        let mut indexer = Indexer::new(db.clone(), runtime.handle().clone(), config).unwrap();
+        indexer.poll().unwrap();
+        let ingredient = runtime.block_on(db.get_ingredient("abricot.eth")).unwrap();
+        assert_eq!(ingredient.owner, Some(address(other)));
+        assert_eq!(ingredient.expiry, 4_000_000_000);
+        assert!(runtime
+            .block_on(db.get_ingredients_by_owner(&address(OWNER)))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            runtime
+                .block_on(db.get_ingredients_by_owner(&address(other)))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // the transfer is orphaned, the name goes back to its registrant
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.fork = (2, 1);
+            chain.logs.truncate(3);
+        }
+        assert_eq!(indexer.handle_reorg().unwrap(), 1);
+        assert_eq!(
+            runtime
+                .block_on(db.get_ingredient("abricot.eth"))
+                .unwrap()
+                .owner,
+            Some(address(OWNER))
+        );
+    }
+
+    #[test]
+    fn test_indexer_waits_for_confirmations() {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 3,
+            logs: vec![
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+                ingredient_completed_log(3, RECIPE, "ail.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut config = config(serve_chain(chain.clone()));
+        config.confirmations = 3;
+        let mut indexer = Indexer::new(db.clone(), runtime.handle().clone(), config).unwrap();
+        indexer.poll().unwrap();
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.status, Status::Ongoing);
+        assert!(recipe
+            .ingredients
+            .iter()
+            .all(|x| x.status == Status::Pending));
+        assert_eq!(recipe.ingredients[0].confirmations, 2);
+
+        chain.lock().unwrap().head = 4;
+        indexer.poll().unwrap();
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.ingredients[0].status, Status::Completed);
+        assert_eq!(recipe.ingredients[1].status, Status::Pending);
+        assert_eq!(recipe.ingredients[1].confirmations, 2);
+
+        chain.lock().unwrap().head = 5;
+        indexer.poll().unwrap();
+        assert_eq!(
+            runtime
+                .block_on(db.get_recipe(1, &address(RECIPE)))
+                .unwrap()
+                .status,
+            Status::Completed
+        );
+    }
+
+    #[test]
+    fn test_indexer_rolls_back_orphaned_blocks() {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 3,
+            logs: vec![
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+                ingredient_completed_log(3, RECIPE, "ail.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain.clone())),
+        )
+        .unwrap();
+        indexer.poll().unwrap();
+        assert_eq!(
+            runtime
+                .block_on(db.get_recipe(1, &address(RECIPE)))
+                .unwrap()
+                .status,
+            Status::Completed
+        );
+
+        // blocks 2 and 3 are replaced, only abricot is found again in block 4
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.head = 4;
+            chain.fork = (2, 1);
+            chain.logs.truncate(1);
+            chain
+                .logs
+                .push(ingredient_completed_log(4, RECIPE, "abricot.eth", OWNER));
+        }
+        assert_eq!(indexer.handle_reorg().unwrap(), 2);
+        assert_eq!(indexer.next_block(), 2);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.status, Status::Ongoing);
+        assert!(recipe
+            .ingredients
+            .iter()
+            .all(|x| x.status == Status::Ongoing));
+        assert_eq!(recipe.last_block, 1);
+
+        assert_eq!(indexer.poll().unwrap(), 4);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.status, Status::Ongoing);
+        assert_eq!(recipe.ingredients[0].status, Status::Completed);
+        assert_eq!(recipe.ingredients[1].status, Status::Ongoing);
+        assert_eq!(
+            runtime
+                .block_on(db.get_latest_block(1))
+                .unwrap()
+                .unwrap()
+                .hash,
+            format!("0x01{:062x}", 4).parse().unwrap()
+        );
+
+        // the creation of the recipe is orphaned as well
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.fork = (1, 2);
+            chain.logs.clear();
+        }
+        indexer.poll().unwrap();
+        assert!(matches!(
+            runtime.block_on(db.get_recipe(1, &address(RECIPE))),
+            Err(MongoRepError::EmptyResponse())
+        ));
+    }
+
+    #[test]
+    fn test_indexer_rollback_keeps_completion_of_a_skipped_log() {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 2,
+            logs: vec![
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain.clone())),
+        )
+        .unwrap();
+        indexer.poll().unwrap();
+
+        // block 3 completes abricot again, the log is skipped
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.head = 3;
+            chain.logs.push(ingredient_completed_log(
+                3,
+                RECIPE,
+                "abricot.eth",
+                "0x00000000000000000000000000000000000000bb",
+            ));
+        }
+        indexer.poll().unwrap();
+        let block = runtime.block_on(db.get_latest_block(1)).unwrap().unwrap();
+        assert_eq!(block.number, 3);
+        assert!(block.changes.is_empty());
+
+        // orphaning block 3 leaves the completion of block 2 as is
+        chain.lock().unwrap().fork = (3, 1);
+        assert_eq!(indexer.handle_reorg().unwrap(), 1);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.ingredients[0].status, Status::Completed);
+        assert_eq!(recipe.ingredients[0].owner, Some(address(OWNER)));
+    }
+
+    #[test]
+    fn test_indexer_leaves_skipped_completions_out_of_the_history() {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 1,
+            logs: vec![recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"])],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain.clone())),
+        )
+        .unwrap();
+        indexer.poll().unwrap();
+        runtime
+            .block_on(db.set_recipe_status(1, &address(RECIPE), Status::Cancelled))
+            .unwrap();
+
+        // the cancelled recipe rejects the completion
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.head = 2;
+            chain
+                .logs
+                .push(ingredient_completed_log(2, RECIPE, "abricot.eth", OWNER));
+        }
+        indexer.poll().unwrap();
+        assert_eq!(
+            runtime
+                .block_on(db.get_recipe_history(1, &address(RECIPE)))
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(runtime
+            .block_on(db.get_leaderboard_at(1, 2))
+            .unwrap()
+            .is_empty());
+        assert!(runtime
+            .block_on(db.get_statistics_at(1, &address(OWNER), 2))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_indexer_skips_recipes_with_unknown_ingredients() {
+        let other = "0x00000000000000000000000000000000000012bb";
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 3,
+            logs: vec![
+                recipe_created_log(1, other, &["abricot.eth", "amande.eth"]),
+                recipe_created_log(1, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(2, other, "abricot.eth", OWNER),
+                ingredient_completed_log(3, RECIPE, "abricot.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            config(serve_chain(chain)),
+        )
+        .unwrap();
+        // the unknown ingredient does not stop the batch
+        assert_eq!(indexer.poll().unwrap(), 3);
+        assert!(runtime.block_on(db.get_recipe(1, &address(other))).is_err());
+        assert!(runtime
+            .block_on(db.get_recipe_history(1, &address(other)))
+            .unwrap()
+            .is_empty());
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.ingredients[0].owner, Some(address(OWNER)));
+    }
+
+    #[test]
+    fn test_indexer_reindexes_from_the_last_canonical_block() {
+        let chain = Arc::new(Mutex::new(Chain {
+            head: 20,
+            logs: vec![
+                recipe_created_log(10, RECIPE, &["abricot.eth", "ail.eth"]),
+                ingredient_completed_log(20, RECIPE, "ail.eth", OWNER),
+            ],
+            id: 1,
+            ..Default::default()
+        }));
+        let db = init_repo();
+        let runtime = Runtime::new().unwrap();
+        let mut indexer = Indexer::new(
+            db.clone(),
+            runtime.handle().clone(),
+            IndexerConfig {
+                batch_size: 100,
+                ..config(serve_chain(chain.clone()))
+            },
+        )
+        .unwrap();
+        indexer.poll().unwrap();
+
+        // only blocks 10 and 20 are recorded, the fork starts in between at 15
+        // where abricot is found on the new branch
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.fork = (15, 1);
+            chain.logs.truncate(1);
+            chain
+                .logs
+                .push(ingredient_completed_log(17, RECIPE, "abricot.eth", OWNER));
+        }
+        assert_eq!(indexer.handle_reorg().unwrap(), 1);
+        assert_eq!(indexer.next_block(), 11);
+        assert_eq!(indexer.poll().unwrap(), 20);
+        let recipe = runtime
+            .block_on(db.get_recipe(1, &address(RECIPE)))
+            .unwrap();
+        assert_eq!(recipe.ingredients[0].status, Status::Completed);
+        assert_eq!(recipe.ingredients[0].block, 17);
+        assert_eq!(recipe.ingredients[1].status, Status::Ongoing);
+    }
+}
@@ -0,0 +1,506 @@
+use super::{event_topic, IndexerError, Log, RecipeEvent};
+use crate::infra::eth::H256;
+use crate::infra::merkle::{from_hex, SIZE};
+use crate::infra::mongo::Deadline;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct AbiParam {
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub indexed: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct AbiEvent {
+    pub name: String,
+    #[serde(default)]
+    pub inputs: Vec<AbiParam>,
+}
+
+/// A decoded event parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Address(String),
+    // bool, integers, fixed size bytes and hashed indexed dynamic values
+    Word([u8; SIZE]),
+    // bytes and string
+    Bytes(Vec<u8>),
+    Array(Vec<Token>),
+}
+
+// an entry of an ABI, only the events are kept
+#[derive(Deserialize)]
+struct AbiEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(flatten)]
+    event: Option<AbiEvent>,
+}
+
+// a plain ABI, or an object holding it such as a build artifact, which can
+// name the factory deploying this version of the contracts
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AbiFile {
+    Abi(Vec<AbiEntry>),
+    Contract {
+        #[serde(default)]
+        factory: Option<String>,
+        abi: Vec<AbiEntry>,
+    },
+}
+
+impl AbiEvent {
+    pub fn signature(&self) -> String {
+        let types: Vec<&str> = self.inputs.iter().map(|x| x.kind.as_str()).collect();
+        format!("{}({})", self.name, types.join(","))
+    }
+
+    pub fn topic(&self) -> String {
+        event_topic(&self.signature())
+    }
+
+    /// Decodes the parameters of the event from the topics and data of `log`.
+    pub fn decode(&self, log: &Log) -> Result<Vec<(String, Token)>, IndexerError> {
+        let invalid =
+            || IndexerError::InvalidLog(format!("{}:{}", log.transaction_hash, log.log_index));
+        let data =
+            hex::decode(log.data.strip_prefix("0x").unwrap_or(&log.data)).map_err(|_| invalid())?;
+        let mut topics = log.topics.iter().skip(1);
+        let mut head = 0;
+        let mut params = vec![];
+        for param in self.inputs.iter() {
+            let token = if param.indexed {
+                let topic = topics.next().ok_or_else(invalid)?;
+                let word = from_hex(topic).map_err(|_| invalid())?;
+                // dynamic values are only indexed by their hash
+                decode_word(&param.kind, &word).unwrap_or(Token::Word(word))
+            } else {
+                let token = decode_param(&param.kind, &data, head).ok_or_else(invalid)?;
+                head += SIZE;
+                token
+            };
+            params.push((param.name.clone(), token));
+        }
+        Ok(params)
+    }
+}
+
+/// The events of every version of the recipe contracts.
+#[derive(Debug, Clone, Default)]
+pub struct ContractAbis {
+    // version, factory and events of each version, in version order
+    versions: Vec<(String, Option<String>, Vec<AbiEvent>)>,
+}
+
+impl ContractAbis {
+    /// Loads every `<version>.json` file of `dir`.
+    pub fn load(dir: &Path) -> Result<Self, IndexerError> {
+        let invalid = |e: &dyn ToString| IndexerError::InvalidAbi(e.to_string());
+        let mut paths = fs::read_dir(dir)
+            .map_err(|e| invalid(&e))?
+            .map(|x| x.map(|x| x.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(&e))?;
+        paths.retain(|x| x.extension().is_some_and(|x| x == "json"));
+        paths.sort();
+        let mut abis = ContractAbis::default();
+        for path in paths {
+            let version = path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .ok_or_else(|| invalid(&path.display()))?;
+            let json = fs::read_to_string(&path).map_err(|e| invalid(&e))?;
+            abis.add(version, &json)?;
+        }
+        if abis.versions.is_empty() {
+            return Err(invalid(&format!("no abi in {}", dir.display())));
+        }
+        Ok(abis)
+    }
+
+    pub fn add(&mut self, version: &str, json: &str) -> Result<(), IndexerError> {
+        let (factory, entries) = match serde_json::from_str(json)
+            .map_err(|e| IndexerError::InvalidAbi(format!("{}: {}", version, e)))?
+        {
+            AbiFile::Abi(entries) => (None, entries),
+            AbiFile::Contract { factory, abi } => (factory.map(|x| x.to_lowercase()), abi),
+        };
+        let events = entries
+            .into_iter()
+            .filter(|x| x.kind == "event")
+            .filter_map(|x| x.event)
+            .filter(|x| RECIPE_EVENTS.contains(&x.name.as_str()))
+            .collect();
+        self.versions.push((version.to_string(), factory, events));
+        Ok(())
+    }
+
+    pub fn versions(&self) -> Vec<&str> {
+        self.versions.iter().map(|x| x.0.as_str()).collect()
+    }
+
+    /// First topics of the recipe events of every version.
+    pub fn topics(&self) -> Vec<String> {
+        self.event_topics(|_| true)
+    }
+
+    /// First topics of the `RecipeCreated` events, emitted by the factories.
+    pub fn factory_topics(&self) -> Vec<String> {
+        self.event_topics(|x| x == RECIPE_CREATED)
+    }
+
+    /// First topics of the events emitted by the recipes themselves.
+    pub fn recipe_topics(&self) -> Vec<String> {
+        self.event_topics(|x| x != RECIPE_CREATED)
+    }
+
+    fn event_topics(&self, name: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut topics: Vec<String> = self
+            .versions
+            .iter()
+            .flat_map(|x| x.2.iter())
+            .filter(|x| name(&x.name))
+            .map(|x| x.topic())
+            .collect();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// Factories announcing the recipes, the ones named by the versions and
+    /// `factories` for the versions which do not name one.
+    pub fn factories(&self, factories: &[String]) -> Vec<String> {
+        let mut addresses: Vec<String> = self.versions.iter().filter_map(|x| x.1.clone()).collect();
+        if self.versions.iter().any(|x| x.1.is_none()) {
+            addresses.extend(factories.iter().cloned());
+        }
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    }
+
+    /// Decodes a recipe log, unknown events are skipped. Recipes can only be
+    /// announced by the factory of their version, or one of `factories` when
+    /// the version does not name one.
+    pub fn decode_log(
+        &self,
+        log: &Log,
+        factories: &[String],
+    ) -> Result<Option<RecipeEvent>, IndexerError> {
+        let topic = match log.topics.first() {
+            Some(topic) => topic.to_lowercase(),
+            None => return Ok(None),
+        };
+        let address = log.address.to_lowercase();
+        let mut found = None;
+        for (version, factory, events) in self.versions.iter() {
+            for event in events.iter().filter(|x| x.topic() == topic) {
+                let announced = match factory {
+                    Some(factory) => *factory == address,
+                    None => factories.contains(&address),
+                };
+                if event.name != RECIPE_CREATED || announced {
+                    // the most recent version wins
+                    found = Some((version.as_str(), event));
+                }
+            }
+        }
+        match found {
+            Some((version, event)) => to_recipe_event(version, event, log).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+const RECIPE_CREATED: &str = "RecipeCreated";
+const INGREDIENT_COMPLETED: &str = "IngredientCompleted";
+const RECIPE_EVENTS: [&str; 2] = [RECIPE_CREATED, INGREDIENT_COMPLETED];
+
+// maps the decoded parameters to the typed event, by parameter name
+fn to_recipe_event(
+    version: &str,
+    event: &AbiEvent,
+    log: &Log,
+) -> Result<RecipeEvent, IndexerError> {
+    let params = event.decode(log)?;
+    let invalid = || {
+        IndexerError::InvalidLog(format!(
+            "{}:{} for {} {}",
+            log.transaction_hash,
+            log.log_index,
+            version,
+            event.signature()
+        ))
+    };
+    let param = |name: &str| params.iter().find(|x| x.0 == name).map(|x| &x.1);
+    let address = |name: &str| match param(name) {
+        Some(Token::Address(address)) => address.parse().map_err(|_| invalid()),
+        _ => Err(invalid()),
+    };
+    let word = |name: &str| match param(name) {
+        Some(Token::Word(word)) => Ok(H256(*word)),
+        _ => Err(invalid()),
+    };
+    // a missing or zero integer is no limit
+    let limit = |name: &str| match param(name) {
+        Some(Token::Word(word)) => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&word[SIZE - 8..]);
+            match i64::try_from(u64::from_be_bytes(bytes)) {
+                Ok(0) => Ok(None),
+                Ok(limit) => Ok(Some(limit)),
+                Err(_) => Err(invalid()),
+            }
+        }
+        Some(_) => Err(invalid()),
+        None => Ok(None),
+    };
+    match event.name.as_str() {
+        RECIPE_CREATED => Ok(RecipeEvent::RecipeCreated {
+            recipe: address("recipe")?,
+            ingredients: match param("ingredients") {
+                Some(Token::Array(tokens)) => tokens
+                    .iter()
+                    .map(|x| match x {
+                        Token::Word(word) => Ok(H256(*word)),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<Vec<H256>, IndexerError>>()?,
+                _ => return Err(invalid()),
+            },
+            version: version.to_string(),
+            deadline: Deadline {
+                block: limit("deadlineBlock")?,
+                timestamp: limit("deadline")?,
+            },
+        }),
+        _ => Ok(RecipeEvent::IngredientCompleted {
+            recipe: log.address.parse().map_err(|_| invalid())?,
+            ingredient: word("ingredient")?,
+            owner: address("owner")?,
+        }),
+    }
+}
+
+fn is_static(kind: &str) -> bool {
+    kind == "address"
+        || kind == "bool"
+        || kind.starts_with("uint")
+        || kind.starts_with("int")
+        || (kind.starts_with("bytes") && kind != "bytes")
+}
+
+fn decode_word(kind: &str, word: &[u8]) -> Option<Token> {
+    if !is_static(kind) || kind.ends_with(']') {
+        return None;
+    }
+    if kind == "address" {
+        return Some(Token::Address(format!("0x{}", hex::encode(&word[12..]))));
+    }
+    Some(Token::Word(word.try_into().ok()?))
+}
+
+fn decode_param(kind: &str, data: &[u8], head: usize) -> Option<Token> {
+    if let Some(inner) = kind.strip_suffix("[]") {
+        let offset = read_usize(data, head)?;
+        let length = read_usize(data, offset)?;
+        (0..length)
+            .map(|i| decode_word(inner, read_word(data, offset + SIZE * (i + 1))?))
+            .collect::<Option<Vec<Token>>>()
+            .map(Token::Array)
+    } else if kind == "bytes" || kind == "string" {
+        let offset = read_usize(data, head)?;
+        let length = read_usize(data, offset)?;
+        let start = offset.checked_add(SIZE)?;
+        data.get(start..start.checked_add(length)?)
+            .map(|x| Token::Bytes(x.to_vec()))
+    } else {
+        decode_word(kind, read_word(data, head)?)
+    }
+}
+
+fn read_word(data: &[u8], offset: usize) -> Option<&[u8]> {
+    data.get(offset..offset.checked_add(SIZE)?)
+}
+
+fn read_usize(data: &[u8], offset: usize) -> Option<usize> {
+    let word = read_word(data, offset)?;
+    // anything that does not fit in 8 bytes cannot be a valid offset or length
+    if word[..SIZE - 8].iter().any(|x| *x != 0) {
+        return None;
+    }
+    usize::try_from(u64::from_be_bytes(word[SIZE - 8..].try_into().ok()?)).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::infra::indexer::events::tests::{
+        ingredient_completed_log, recipe_created_log, FACTORY,
+    };
+    use crate::infra::merkle::get_namehash;
+    use std::path::PathBuf;
+
+    pub fn abis() -> ContractAbis {
+        ContractAbis::load(&PathBuf::from("abi")).unwrap()
+    }
+
+    // a second version adding the deadline of the recipe, deployed by its own factory
+    const V2: &str = r#"{
+        "factory": "0x00000000000000000000000000000000000000f2",
+        "abi": [
+            {"type": "constructor", "inputs": []},
+            {"type": "event", "name": "RecipeCreated", "anonymous": false, "inputs": [
+                {"name": "recipe", "type": "address", "indexed": true},
+                {"name": "ingredients", "type": "bytes32[]", "indexed": false},
+                {"name": "deadline", "type": "uint256", "indexed": false}
+            ]},
+            {"type": "event", "name": "IngredientCompleted", "anonymous": false, "inputs": [
+                {"name": "ingredient", "type": "bytes32", "indexed": true},
+                {"name": "owner", "type": "address", "indexed": true}
+            ]}
+        ]
+    }"#;
+
+    fn hash(domain: &str) -> H256 {
+        H256(get_namehash(domain.to_string()))
+    }
+
+    #[test]
+    fn test_load_abis() {
+        let abis = abis();
+        assert_eq!(abis.versions(), vec!["v1"]);
+        assert_eq!(abis.topics().len(), 2);
+        assert!(abis
+            .topics()
+            .contains(&event_topic("RecipeCreated(address,bytes32[])")));
+        assert!(ContractAbis::load(&PathBuf::from("src")).is_err());
+        assert!(ContractAbis::default().add("v0", "{}").is_err());
+    }
+
+    #[test]
+    fn test_decode_recipe_created() {
+        let recipe = "0x00000000000000000000000000000000000000aa";
+        let log = recipe_created_log(3, recipe, &["abricot.eth", "ail.eth"]);
+        let factories = vec![FACTORY.to_string()];
+        assert_eq!(
+            abis().decode_log(&log, &factories).unwrap(),
+            Some(RecipeEvent::RecipeCreated {
+                recipe: recipe.parse().unwrap(),
+                ingredients: vec![hash("abricot.eth"), hash("ail.eth")],
+                version: String::from("v1"),
+                deadline: Deadline::default(),
+            })
+        );
+        // not announced by a factory
+        assert_eq!(abis().decode_log(&log, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_ingredient_completed() {
+        let recipe = "0x00000000000000000000000000000000000000aa";
+        let owner = "0x00000000000000000000000000000000000000bb";
+        let log = ingredient_completed_log(4, recipe, "abricot.eth", owner);
+        assert_eq!(
+            abis().decode_log(&log, &[]).unwrap(),
+            Some(RecipeEvent::IngredientCompleted {
+                recipe: recipe.parse().unwrap(),
+                ingredient: hash("abricot.eth"),
+                owner: owner.parse().unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_versions_side_by_side() {
+        let mut abis = abis();
+        abis.add("v2", V2).unwrap();
+        assert_eq!(abis.topics().len(), 3);
+        assert_eq!(abis.factory_topics().len(), 2);
+        assert_eq!(abis.recipe_topics().len(), 1);
+        let factories = vec![FACTORY.to_string()];
+        assert_eq!(
+            abis.factories(&factories),
+            vec![
+                String::from("0x00000000000000000000000000000000000000f2"),
+                FACTORY.to_string()
+            ]
+        );
+
+        let recipe = "0x00000000000000000000000000000000000000aa";
+        let log = recipe_created_log(3, recipe, &["abricot.eth"]);
+        assert!(matches!(
+            abis.decode_log(&log, &factories).unwrap(),
+            Some(RecipeEvent::RecipeCreated { version, .. }) if version == "v1"
+        ));
+
+        // v2 logs carry the deadline after the ingredients
+        let mut log = recipe_created_log(3, recipe, &["abricot.eth", "ail.eth"]);
+        log.address = String::from("0x00000000000000000000000000000000000000F2");
+        log.topics[0] = event_topic("RecipeCreated(address,bytes32[],uint256)");
+        let data = &log.data[2..];
+        log.data = format!("0x{:064x}{:064x}{}", 0x40, 1_700_000_000, &data[64..]);
+        assert_eq!(
+            abis.decode_log(&log, &factories).unwrap(),
+            Some(RecipeEvent::RecipeCreated {
+                recipe: recipe.parse().unwrap(),
+                ingredients: vec![hash("abricot.eth"), hash("ail.eth")],
+                version: String::from("v2"),
+                deadline: Deadline {
+                    block: None,
+                    timestamp: Some(1_700_000_000),
+                },
+            })
+        );
+        // only the v2 factory announces v2 recipes
+        log.address = FACTORY.to_string();
+        assert_eq!(abis.decode_log(&log, &factories).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_params() {
+        let event: AbiEvent = serde_json::from_str(
+            r#"{"name": "Test", "inputs": [
+                {"name": "name", "type": "string", "indexed": true},
+                {"name": "flag", "type": "bool"},
+                {"name": "label", "type": "string"},
+                {"name": "owners", "type": "address[]"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(event.signature(), "Test(string,bool,string,address[])");
+        let mut log = recipe_created_log(1, "0x00000000000000000000000000000000000000aa", &[]);
+        log.topics = vec![event.topic(), hash("abricot.eth").to_string()];
+        // "foo" is left aligned in its word
+        log.data = format!(
+            "0x{:064x}{:064x}{:064x}{:064x}{:0<64}{:064x}{:064x}",
+            1, 0x60, 0xa0, 3, "666f6f", 1, 0xbb
+        );
+        let params = event.decode(&log).unwrap();
+        assert_eq!(params[0].1, Token::Word(hash("abricot.eth").0));
+        assert_eq!(
+            params[1].1,
+            Token::Word(from_hex(&format!("0x{:064x}", 1)).unwrap())
+        );
+        assert_eq!(params[2].1, Token::Bytes(b"foo".to_vec()));
+        assert_eq!(
+            params[3].1,
+            Token::Array(vec![Token::Address(format!("0x{:040x}", 0xbb))])
+        );
+
+        log.data.truncate(log.data.len() - 2);
+        assert!(matches!(
+            event.decode(&log),
+            Err(IndexerError::InvalidLog(_))
+        ));
+        log.topics.pop();
+        assert!(event.decode(&log).is_err());
+    }
+}
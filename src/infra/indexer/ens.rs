@@ -0,0 +1,212 @@
+use super::{AbiEvent, IndexerError, Log, Token};
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::{get_namehash, keccak256, SIZE};
+
+/// The events of the .eth registrar giving the registrant and the expiry of
+/// the ingredients. Names are identified by the hash of their label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnsEvent {
+    Transfer {
+        label_hash: H256,
+        owner: Address,
+    },
+    NameRegistered {
+        label_hash: H256,
+        owner: Address,
+        expires: i64,
+    },
+    NameRenewed {
+        label_hash: H256,
+        expires: i64,
+    },
+}
+
+// events of the BaseRegistrarImplementation contract
+const REGISTRAR_ABI: &str = r#"[
+    {"name": "Transfer", "inputs": [
+        {"name": "from", "type": "address", "indexed": true},
+        {"name": "to", "type": "address", "indexed": true},
+        {"name": "tokenId", "type": "uint256", "indexed": true}
+    ]},
+    {"name": "NameRegistered", "inputs": [
+        {"name": "id", "type": "uint256", "indexed": true},
+        {"name": "owner", "type": "address", "indexed": true},
+        {"name": "expires", "type": "uint256", "indexed": false}
+    ]},
+    {"name": "NameRenewed", "inputs": [
+        {"name": "id", "type": "uint256", "indexed": true},
+        {"name": "expires", "type": "uint256", "indexed": false}
+    ]}
+]"#;
+
+/// The .eth registrar deployed at `address`.
+#[derive(Debug, Clone)]
+pub struct EnsRegistrar {
+    address: String,
+    events: Vec<AbiEvent>,
+}
+
+impl EnsRegistrar {
+    pub fn new(address: &str) -> Self {
+        EnsRegistrar {
+            address: address.to_lowercase(),
+            events: serde_json::from_str(REGISTRAR_ABI).unwrap(),
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        self.events.iter().map(|x| x.topic()).collect()
+    }
+
+    /// Decodes a log of the registrar, the logs of other contracts are skipped.
+    pub fn decode_log(&self, log: &Log) -> Result<Option<EnsEvent>, IndexerError> {
+        if !log.address.eq_ignore_ascii_case(&self.address) {
+            return Ok(None);
+        }
+        let topic = match log.topics.first() {
+            Some(topic) => topic.to_lowercase(),
+            None => return Ok(None),
+        };
+        let event = match self.events.iter().find(|x| x.topic() == topic) {
+            Some(event) => event,
+            None => return Ok(None),
+        };
+        let params = event.decode(log)?;
+        let invalid =
+            || IndexerError::InvalidLog(format!("{}:{}", log.transaction_hash, log.log_index));
+        let param = |name: &str| params.iter().find(|x| x.0 == name).map(|x| &x.1);
+        let address = |name: &str| match param(name) {
+            Some(Token::Address(address)) => address.parse::<Address>().map_err(|_| invalid()),
+            _ => Err(invalid()),
+        };
+        let word = |name: &str| match param(name) {
+            Some(Token::Word(word)) => Ok(H256(*word)),
+            _ => Err(invalid()),
+        };
+        // expiries are unix timestamps, far below the 8 last bytes
+        let timestamp = |name: &str| {
+            let word = word(name)?;
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&word.0[SIZE - 8..]);
+            i64::try_from(u64::from_be_bytes(bytes)).map_err(|_| invalid())
+        };
+        Ok(Some(match event.name.as_str() {
+            "Transfer" => EnsEvent::Transfer {
+                label_hash: word("tokenId")?,
+                owner: address("to")?,
+            },
+            "NameRegistered" => EnsEvent::NameRegistered {
+                label_hash: word("id")?,
+                owner: address("owner")?,
+                expires: timestamp("expires")?,
+            },
+            _ => EnsEvent::NameRenewed {
+                label_hash: word("id")?,
+                expires: timestamp("expires")?,
+            },
+        }))
+    }
+}
+
+/// Namehash of the .eth name whose label hashes to `label_hash`, the hash of
+/// the ingredients.
+pub fn eth_namehash(label_hash: &H256) -> H256 {
+    let eth = get_namehash(String::from("eth"));
+    H256(keccak256(&[eth, label_hash.0].concat()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::infra::indexer::event_topic;
+
+    pub const REGISTRAR: &str = "0x57f1887a8bf19b14fc0df6fd9b2acc9af147ea85";
+
+    fn word(bytes: &[u8]) -> String {
+        let mut word = [0u8; SIZE];
+        word[SIZE - bytes.len()..].copy_from_slice(bytes);
+        hex::encode(word)
+    }
+
+    fn label_hash(domain: &str) -> H256 {
+        H256(keccak256(domain.trim_end_matches(".eth").as_bytes()))
+    }
+
+    pub fn name_registered_log(block: u64, domain: &str, owner: &str, expires: u64) -> Log {
+        Log {
+            address: REGISTRAR.to_string(),
+            topics: vec![
+                event_topic("NameRegistered(uint256,address,uint256)"),
+                label_hash(domain).to_string(),
+                format!("0x{}", word(&hex::decode(&owner[2..]).unwrap())),
+            ],
+            data: format!("0x{}", word(&expires.to_be_bytes())),
+            block_number: format!("{:#x}", block),
+            block_hash: format!("0x{}", word(&block.to_be_bytes())),
+            transaction_hash: format!("0x{}", word(&[0x03])),
+            log_index: String::from("0x2"),
+            removed: false,
+            block_timestamp: None,
+        }
+    }
+
+    pub fn transfer_log(block: u64, domain: &str, from: &str, to: &str) -> Log {
+        let address = |x: &str| format!("0x{}", word(&hex::decode(&x[2..]).unwrap()));
+        Log {
+            address: REGISTRAR.to_string(),
+            topics: vec![
+                event_topic("Transfer(address,address,uint256)"),
+                address(from),
+                address(to),
+                label_hash(domain).to_string(),
+            ],
+            data: String::from("0x"),
+            block_number: format!("{:#x}", block),
+            block_hash: format!("0x{}", word(&block.to_be_bytes())),
+            transaction_hash: format!("0x{}", word(&[0x04])),
+            log_index: String::from("0x3"),
+            removed: false,
+            block_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_decode_registrar_logs() {
+        let registrar = EnsRegistrar::new(REGISTRAR);
+        let owner = "0xc5e4ec0073631fa872334749381e4d514da130f8";
+        let log = name_registered_log(1, "abricot.eth", owner, 1_700_000_000);
+        assert_eq!(
+            registrar.decode_log(&log).unwrap(),
+            Some(EnsEvent::NameRegistered {
+                label_hash: label_hash("abricot.eth"),
+                owner: owner.parse().unwrap(),
+                expires: 1_700_000_000,
+            })
+        );
+        let log = transfer_log(2, "abricot.eth", owner, REGISTRAR);
+        assert_eq!(
+            registrar.decode_log(&log).unwrap(),
+            Some(EnsEvent::Transfer {
+                label_hash: label_hash("abricot.eth"),
+                owner: REGISTRAR.parse().unwrap(),
+            })
+        );
+
+        // the transfers of other tokens are not ens names
+        let mut log = transfer_log(2, "abricot.eth", owner, REGISTRAR);
+        log.address = String::from("0x0000000000000000000000000000000000000001");
+        assert_eq!(registrar.decode_log(&log).unwrap(), None);
+    }
+
+    #[test]
+    fn test_eth_namehash() {
+        assert_eq!(
+            eth_namehash(&label_hash("abricot.eth")),
+            H256(get_namehash(String::from("abricot.eth")))
+        );
+    }
+}
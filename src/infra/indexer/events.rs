@@ -0,0 +1,98 @@
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::{keccak256, to_hex};
+use crate::infra::mongo::Deadline;
+
+/// The recipe contract events, decoded from their logs with the ABI of the
+/// contract version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipeEvent {
+    // emitted by the factory when a recipe contract is deployed
+    RecipeCreated {
+        recipe: Address,
+        ingredients: Vec<H256>,
+        version: String,
+        // read from the `deadline` and `deadlineBlock` parameters, when the
+        // version has them
+        deadline: Deadline,
+    },
+    // emitted by a recipe contract when one of its ingredients is found
+    IngredientCompleted {
+        recipe: Address,
+        ingredient: H256,
+        owner: Address,
+    },
+}
+
+/// First topic of the logs emitted for the event `signature`.
+pub fn event_topic(signature: &str) -> String {
+    to_hex(&keccak256(signature.as_bytes()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::infra::indexer::Log;
+    use crate::infra::merkle::{get_namehash, SIZE};
+
+    pub const FACTORY: &str = "0x5fbdb2315678afecb367f032d93f642f64180aa3";
+
+    fn word(bytes: &[u8]) -> String {
+        let mut word = [0u8; SIZE];
+        word[SIZE - bytes.len()..].copy_from_slice(bytes);
+        hex::encode(word)
+    }
+
+    fn address_topic(address: &str) -> String {
+        format!("0x{}", word(&hex::decode(&address[2..]).unwrap()))
+    }
+
+    pub fn recipe_created_log(block: u64, recipe: &str, ingredients: &[&str]) -> Log {
+        let mut data = String::from("0x");
+        data.push_str(&word(&[0x20]));
+        data.push_str(&word(&[ingredients.len() as u8]));
+        for ingredient in ingredients {
+            data.push_str(&hex::encode(get_namehash(ingredient.to_string())));
+        }
+        Log {
+            address: FACTORY.to_string(),
+            topics: vec![
+                event_topic("RecipeCreated(address,bytes32[])"),
+                address_topic(recipe),
+            ],
+            data,
+            block_number: format!("{:#x}", block),
+            block_hash: format!("0x{}", word(&block.to_be_bytes())),
+            transaction_hash: format!("0x{}", word(&[0x01])),
+            log_index: String::from("0x0"),
+            removed: false,
+            block_timestamp: None,
+        }
+    }
+
+    pub fn ingredient_completed_log(block: u64, recipe: &str, domain: &str, owner: &str) -> Log {
+        Log {
+            address: recipe.to_string(),
+            topics: vec![
+                event_topic("IngredientCompleted(bytes32,address)"),
+                to_hex(&get_namehash(domain.to_string())),
+                address_topic(owner),
+            ],
+            data: String::from("0x"),
+            block_number: format!("{:#x}", block),
+            block_hash: format!("0x{}", word(&block.to_be_bytes())),
+            // one transaction per completion, their logs are told apart by it
+            transaction_hash: format!("0x{}", word(&[0x02, block as u8])),
+            log_index: String::from("0x1"),
+            removed: false,
+            block_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_event_topic() {
+        assert_eq!(
+            event_topic("Transfer(address,address,uint256)"),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::infra::eth::{Address, H256};
+use crate::infra::mongo::{Block, Completion, Deadline, HistoryEvent, MongoRepError, Repository};
+use std::fmt;
+
+/// A write of the indexer to the database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mutation {
+    AddRecipe {
+        chain_id: i64,
+        address: Address,
+        ingredients: Vec<H256>,
+        block: i64,
+        version: String,
+        deadline: Deadline,
+    },
+    UpdateRecipe {
+        chain_id: i64,
+        address: Address,
+        ingredient: H256,
+        completion: Completion,
+    },
+    AddHistoryEvent(HistoryEvent),
+    UpdateIngredientOwner {
+        hash: H256,
+        owner: Option<Address>,
+        expiry: Option<i64>,
+    },
+    ConfirmIngredients {
+        chain_id: i64,
+        head: i64,
+        confirmations: i64,
+    },
+    AddBlock(Block),
+    RollbackBlock(Block),
+    PruneBlocks {
+        chain_id: i64,
+        number: i64,
+    },
+}
+
+impl Mutation {
+    /// Applies the mutation, returns false when the database left it aside,
+    /// e.g. a completion of an ingredient which is already completed.
+    pub async fn apply(&self, db: &dyn Repository) -> Result<bool, MongoRepError> {
+        Ok(match self {
+            Mutation::AddRecipe {
+                chain_id,
+                address,
+                ingredients,
+                block,
+                version,
+                deadline,
+            } => {
+                db.add_recipe(*chain_id, address, ingredients, *block, version, deadline)
+                    .await?
+            }
+            Mutation::UpdateRecipe {
+                chain_id,
+                address,
+                ingredient,
+                completion,
+            } => {
+                db.update_recipe(*chain_id, address, ingredient, completion)
+                    .await?
+            }
+            Mutation::AddHistoryEvent(event) => db.add_history_event(event).await?,
+            Mutation::UpdateIngredientOwner {
+                hash,
+                owner,
+                expiry,
+            } => {
+                db.update_ingredient_owner(hash, owner.as_ref(), *expiry)
+                    .await?;
+                true
+            }
+            Mutation::ConfirmIngredients {
+                chain_id,
+                head,
+                confirmations,
+            } => {
+                db.confirm_ingredients(*chain_id, *head, *confirmations)
+                    .await?;
+                true
+            }
+            Mutation::AddBlock(block) => db.add_block(block).await?,
+            Mutation::RollbackBlock(block) => db.rollback_block(block).await?,
+            Mutation::PruneBlocks { chain_id, number } => {
+                db.prune_blocks(*chain_id, *number).await?;
+                true
+            }
+        })
+    }
+}
+
+// one line per mutation, named after the `Repository` method applying it
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mutation::AddRecipe {
+                chain_id,
+                address,
+                ingredients,
+                block,
+                version,
+                deadline,
+            } => write!(
+                f,
+                "add_recipe chain={} address={} block={} version={} {:?} ingredients={}",
+                chain_id,
+                address,
+                block,
+                version,
+                deadline,
+                ingredients
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            Mutation::UpdateRecipe {
+                chain_id,
+                address,
+                ingredient,
+                completion,
+            } => write!(
+                f,
+                "update_recipe chain={} address={} block={} ingredient={} owner={} timestamp={} tx={}",
+                chain_id,
+                address,
+                completion.block,
+                ingredient,
+                completion.owner,
+                completion.timestamp,
+                completion.transaction_hash
+            ),
+            Mutation::AddHistoryEvent(event) => write!(
+                f,
+                "add_history_event chain={} address={} block={} tx={} log={} {:?}",
+                event.chain_id,
+                event.address,
+                event.block,
+                event.transaction_hash,
+                event.log_index,
+                event.kind
+            ),
+            Mutation::UpdateIngredientOwner {
+                hash,
+                owner,
+                expiry,
+            } => write!(
+                f,
+                "update_ingredient_owner hash={} owner={} expiry={}",
+                hash,
+                owner.map_or(String::from("-"), |x| x.to_string()),
+                expiry.map_or(String::from("-"), |x| x.to_string())
+            ),
+            Mutation::ConfirmIngredients {
+                chain_id,
+                head,
+                confirmations,
+            } => write!(
+                f,
+                "confirm_ingredients chain={} head={} confirmations={}",
+                chain_id, head, confirmations
+            ),
+            Mutation::AddBlock(block) => write!(
+                f,
+                "add_block chain={} number={} hash={} changes={}",
+                block.chain_id,
+                block.number,
+                block.hash,
+                block.changes.len()
+            ),
+            Mutation::RollbackBlock(block) => write!(
+                f,
+                "rollback_block chain={} number={} hash={}",
+                block.chain_id, block.number, block.hash
+            ),
+            Mutation::PruneBlocks { chain_id, number } => {
+                write!(f, "prune_blocks chain={} below={}", chain_id, number)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mutation_display() {
+        let mutation = Mutation::UpdateRecipe {
+            chain_id: 1,
+            address: Address([0xaa; 20]),
+            ingredient: H256([0x01; 32]),
+            completion: Completion {
+                owner: Address([0xbb; 20]),
+                block: 3,
+                timestamp: 36,
+                transaction_hash: H256([0x02; 32]),
+            },
+        };
+        assert_eq!(
+            mutation.to_string(),
+            format!(
+                "update_recipe chain=1 address={} block=3 ingredient=0x{} owner={} timestamp=36 tx=0x{}",
+                Address([0xaa; 20]),
+                "01".repeat(32),
+                Address([0xbb; 20]),
+                "02".repeat(32)
+            )
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use super::{IndexerError, Log};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Reads a recorded NDJSON file of `eth_getLogs` results. Each line holds a
+/// result array, a whole JSON-RPC response or a single log, blank lines are skipped.
+pub fn read_logs(path: &Path) -> Result<Vec<Log>, IndexerError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| IndexerError::InvalidLogFile(format!("{}: {}", path.display(), e)))?;
+    parse_logs(&content)
+}
+
+pub fn parse_logs(content: &str) -> Result<Vec<Log>, IndexerError> {
+    let mut logs = vec![];
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |e: serde_json::Error| {
+            IndexerError::InvalidLogFile(format!("line {}: {}", number + 1, e))
+        };
+        let value: Value = serde_json::from_str(line).map_err(invalid)?;
+        let value = match value {
+            Value::Object(mut response) if response.contains_key("result") => {
+                response.remove("result").unwrap_or_default()
+            }
+            value => value,
+        };
+        match value {
+            Value::Array(_) => {
+                logs.extend(serde_json::from_value::<Vec<Log>>(value).map_err(invalid)?)
+            }
+            value => logs.push(serde_json::from_value(value).map_err(invalid)?),
+        }
+    }
+    Ok(logs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::indexer::events::tests::{ingredient_completed_log, recipe_created_log};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_logs() {
+        let created = recipe_created_log(
+            1,
+            "0x00000000000000000000000000000000000012aa",
+            &["abricot.eth", "ail.eth"],
+        );
+        let mut completed = ingredient_completed_log(
+            2,
+            "0x00000000000000000000000000000000000012aa",
+            "abricot.eth",
+            "0xc5e4ec0073631fa872334749381e4d514da130f8",
+        );
+        // the timestamp of the block is kept when the node returns it
+        completed.block_timestamp = Some(String::from("0x18"));
+        assert_eq!(completed.block_timestamp().unwrap(), Some(24));
+        let content = format!(
+            "{}\n\n{}\n{}\n",
+            json!([created]),
+            json!({"jsonrpc": "2.0", "id": 1, "result": [completed]}),
+            json!(created)
+        );
+        assert_eq!(
+            parse_logs(&content).unwrap(),
+            vec![created.clone(), completed, created]
+        );
+        assert!(matches!(
+            parse_logs("[]\n{\"address\": 1}"),
+            Err(IndexerError::InvalidLogFile(e)) if e.starts_with("line 2")
+        ));
+    }
+}
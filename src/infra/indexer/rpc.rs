@@ -0,0 +1,302 @@
+use super::IndexerError;
+use reqwest::blocking::Client;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A log as returned by `eth_getLogs`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_number: String,
+    pub block_hash: String,
+    pub transaction_hash: String,
+    pub log_index: String,
+    #[serde(default)]
+    pub removed: bool,
+    // only returned by some nodes, the block header is read otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_timestamp: Option<String>,
+}
+
+impl Log {
+    pub fn block_number(&self) -> Result<u64, IndexerError> {
+        parse_quantity(&self.block_number)
+    }
+
+    pub fn log_index(&self) -> Result<u64, IndexerError> {
+        parse_quantity(&self.log_index)
+    }
+
+    pub fn block_timestamp(&self) -> Result<Option<u64>, IndexerError> {
+        self.block_timestamp
+            .as_deref()
+            .map(parse_quantity)
+            .transpose()
+    }
+}
+
+/// The fields of a block header needed to follow the canonical chain and to
+/// date the logs.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockHeader {
+    pub number: String,
+    pub hash: String,
+    pub parent_hash: String,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+}
+
+/// Minimal Ethereum JSON-RPC client over HTTP.
+pub struct RpcClient {
+    url: String,
+    client: Client,
+    id: AtomicU64,
+}
+
+impl RpcClient {
+    pub fn new(url: String) -> Self {
+        RpcClient {
+            url,
+            client: Client::new(),
+            id: AtomicU64::new(1),
+        }
+    }
+
+    fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, IndexerError> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": self.id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .and_then(|x| x.json())
+            .map_err(|e| IndexerError::RpcError(e.to_string()))?;
+        if let Some(error) = response.get("error") {
+            return Err(IndexerError::RpcError(error.to_string()));
+        }
+        match response.get("result") {
+            Some(result) => serde_json::from_value(result.clone())
+                .map_err(|e| IndexerError::InvalidResponse(e.to_string())),
+            None => Err(IndexerError::InvalidResponse(response.to_string())),
+        }
+    }
+
+    pub fn chain_id(&self) -> Result<u64, IndexerError> {
+        let chain_id: String = self.request("eth_chainId", json!([]))?;
+        parse_quantity(&chain_id)
+    }
+
+    pub fn block_number(&self) -> Result<u64, IndexerError> {
+        let number: String = self.request("eth_blockNumber", json!([]))?;
+        parse_quantity(&number)
+    }
+
+    /// Header of the canonical block at `number`, `None` when not mined yet.
+    pub fn get_block(&self, number: u64) -> Result<Option<BlockHeader>, IndexerError> {
+        self.request("eth_getBlockByNumber", json!([to_quantity(number), false]))
+    }
+
+    /// Logs of the contracts at `addresses` in blocks `from..=to` whose first
+    /// topic is one of `topics`.
+    pub fn get_logs(
+        &self,
+        addresses: &[String],
+        from: u64,
+        to: u64,
+        topics: &[String],
+    ) -> Result<Vec<Log>, IndexerError> {
+        // an empty address list is no filter at all for the nodes
+        if addresses.is_empty() {
+            return Ok(vec![]);
+        }
+        self.request(
+            "eth_getLogs",
+            json!([{
+                "address": addresses,
+                "fromBlock": to_quantity(from),
+                "toBlock": to_quantity(to),
+                "topics": [topics],
+            }]),
+        )
+    }
+
+    /// Logs of the contract at `address` in blocks `from..=to` whose first
+    /// topic is one of `topics`.
+    pub fn get_contract_logs(
+        &self,
+        address: &str,
+        from: u64,
+        to: u64,
+        topics: &[String],
+    ) -> Result<Vec<Log>, IndexerError> {
+        self.request(
+            "eth_getLogs",
+            json!([{
+                "address": address,
+                "fromBlock": to_quantity(from),
+                "toBlock": to_quantity(to),
+                "topics": [topics],
+            }]),
+        )
+    }
+}
+
+pub fn parse_quantity(value: &str) -> Result<u64, IndexerError> {
+    let digits = value
+        .strip_prefix("0x")
+        .ok_or_else(|| IndexerError::InvalidResponse(value.to_string()))?;
+    u64::from_str_radix(digits, 16).map_err(|_| IndexerError::InvalidResponse(value.to_string()))
+}
+
+pub fn to_quantity(value: u64) -> String {
+    format!("{:#x}", value)
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Serves JSON-RPC requests on a local port, answering each call with the
+    /// result of `handler(method, params)`, and returns the url of the server.
+    pub fn serve<F>(handler: F) -> String
+    where
+        F: Fn(&str, &Value) -> Result<Value, Value> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                handle(stream, &handler);
+            }
+        });
+        url
+    }
+
+    fn handle<F>(mut stream: TcpStream, handler: &F)
+    where
+        F: Fn(&str, &Value) -> Result<Value, Value>,
+    {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let response = match handler(request["method"].as_str().unwrap(), &request["params"]) {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+            Err(error) => json!({"jsonrpc": "2.0", "id": request["id"], "error": error}),
+        }
+        .to_string();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.len(),
+            response
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(parse_quantity("0x0").unwrap(), 0);
+        assert_eq!(parse_quantity("0x1b4").unwrap(), 436);
+        assert_eq!(to_quantity(436), "0x1b4");
+        assert!(parse_quantity("1b4").is_err());
+        assert!(parse_quantity("0xzz").is_err());
+    }
+
+    #[test]
+    fn test_rpc_client_against_mock() {
+        let url = mock::serve(|method, params| match method {
+            "eth_chainId" => Ok(json!("0xa")),
+            "eth_blockNumber" => Ok(json!("0x10")),
+            "eth_getLogs" => {
+                assert_eq!(params[0]["fromBlock"], "0x1");
+                assert_eq!(params[0]["toBlock"], "0x10");
+                assert_eq!(params[0]["topics"], json!([["0xaa"]]));
+                assert_eq!(params[0]["address"], json!(["0x01"]));
+                Ok(json!([{
+                    "address": "0x01",
+                    "topics": ["0xaa"],
+                    "data": "0x",
+                    "blockNumber": "0x2",
+                    "blockHash": "0x02",
+                    "transactionHash": "0x03",
+                    "logIndex": "0x1",
+                }]))
+            }
+            _ => Err(json!({"code": -32601, "message": "method not found"})),
+        });
+        let rpc = RpcClient::new(url);
+        assert_eq!(rpc.chain_id().unwrap(), 10);
+        assert_eq!(rpc.block_number().unwrap(), 16);
+        let logs = rpc
+            .get_logs(&[String::from("0x01")], 1, 16, &[String::from("0xaa")])
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_number().unwrap(), 2);
+        assert_eq!(logs[0].log_index().unwrap(), 1);
+        assert!(!logs[0].removed);
+        assert!(rpc
+            .get_logs(&[], 1, 16, &[String::from("0xaa")])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rpc_client_get_block() {
+        let url = mock::serve(|method, params| match (method, params[0].as_str()) {
+            ("eth_getBlockByNumber", Some("0x2")) => Ok(json!({
+                "number": "0x2",
+                "hash": "0x02",
+                "parentHash": "0x01",
+                "transactions": [],
+            })),
+            ("eth_getBlockByNumber", _) => Ok(Value::Null),
+            _ => Err(json!({"code": -32601, "message": "method not found"})),
+        });
+        let rpc = RpcClient::new(url);
+        let block = rpc.get_block(2).unwrap().unwrap();
+        assert_eq!(block.hash, "0x02");
+        assert_eq!(block.parent_hash, "0x01");
+        assert_eq!(rpc.get_block(3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rpc_client_error() {
+        let url = mock::serve(|_, _| Err(json!({"code": -32000, "message": "boom"})));
+        let rpc = RpcClient::new(url);
+        assert!(matches!(
+            rpc.block_number(),
+            Err(IndexerError::RpcError(e)) if e.contains("boom")
+        ));
+    }
+}
@@ -1,12 +1,35 @@
-use hex_literal::hex;
-use merkletree::hash::{Algorithm, Hashable};
-use merkletree::merkle::{Element, MerkleTree};
-use merkletree::store::Store;
+use merkletree::hash::Algorithm;
+use merkletree::merkle::{next_pow2, Element};
+use merkletree::store::{DiskStore, Store, StoreConfig, VecStore};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
 use std::hash::Hasher;
+use std::path::PathBuf;
+use std::str::FromStr;
+use thiserror::Error;
 use tiny_keccak::{Hasher as kHasher, Keccak};
-use typenum::Unsigned;
 
-// TODO currently creation of a tree based on [u8;SIZE] is not working
+#[derive(Error, Debug)]
+pub enum MerkleError {
+    #[error("cannot build a merkle tree without leaves")]
+    EmptyTree(),
+    #[error("could not build merkle tree: {0}")]
+    InvalidTree(String),
+    #[error("missing leaf for domain {0}")]
+    MissingLeaf(String),
+    #[error("could not generate proof for domain {0}")]
+    InvalidProof(String),
+    #[error("invalid 32 bytes hex hash {0}")]
+    InvalidHash(String),
+    #[error("could not persist merkle tree: {0}")]
+    InvalidStore(String),
+    #[error("unknown hash function {0}")]
+    UnknownHashFunction(String),
+    #[error("multiproofs need the sorted pair mode")]
+    UnsortedMultiProof(),
+}
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug, Default)]
 pub struct Item([u8; SIZE]);
@@ -34,19 +57,68 @@ impl Element for Item {
     }
 }
 
-pub struct Keccak256Hasher {
-    engine: Keccak,
+/// 32 bytes hash function used for the namehashes and the tree nodes.
+pub trait HashFunction: Clone + Default + Send + Sync {
+    fn update(&mut self, bytes: &[u8]);
+
+    fn finalize(self) -> [u8; SIZE];
+
+    fn digest(bytes: &[u8]) -> [u8; SIZE] {
+        let mut hasher = Self::default();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+}
+
+#[derive(Clone)]
+pub struct Keccak256(Keccak);
+
+impl Default for Keccak256 {
+    fn default() -> Self {
+        Keccak256(Keccak::v256())
+    }
 }
 
-impl Keccak256Hasher {
+impl HashFunction for Keccak256 {
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes)
+    }
+
+    fn finalize(self) -> [u8; SIZE] {
+        let mut output = [0u8; SIZE];
+        self.0.finalize(&mut output);
+        output
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl HashFunction for Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        Digest::update(&mut self.0, bytes)
+    }
+
+    fn finalize(self) -> [u8; SIZE] {
+        self.0.finalize().into()
+    }
+}
+
+/// merkletree's default hashing, prefixing leaves with 0x00 and nodes with
+/// 0x01.
+pub struct MerkleHasher<H: HashFunction> {
+    engine: H,
+}
+
+impl<H: HashFunction> MerkleHasher<H> {
     pub fn new() -> Self {
-        return Keccak256Hasher {
-            engine: Keccak::v256(),
-        };
+        MerkleHasher {
+            engine: H::default(),
+        }
     }
 }
 
-impl Hasher for Keccak256Hasher {
+impl<H: HashFunction> Hasher for MerkleHasher<H> {
     fn finish(&self) -> u64 {
         unimplemented!(
             "Hasher's contract (finish function is not used) is deliberately broken by design"
@@ -58,20 +130,148 @@ impl Hasher for Keccak256Hasher {
     }
 }
 
-impl Default for Keccak256Hasher {
+impl<H: HashFunction> Default for MerkleHasher<H> {
     fn default() -> Self {
-        return Keccak256Hasher::new();
+        MerkleHasher::new()
     }
 }
 
-impl Algorithm<Item> for Keccak256Hasher {
+impl<H: HashFunction> Algorithm<Item> for MerkleHasher<H> {
     fn hash(&mut self) -> Item {
-        let mut result = Item::default();
-        let item_size = result.0.len();
-        let mut output = [0u8; SIZE];
-        self.engine.clone().finalize(&mut output);
-        result.0.copy_from_slice(&output.as_slice()[0..item_size]);
-        result
+        Item(self.engine.clone().finalize())
+    }
+}
+
+/// Hashing compatible with OpenZeppelin's `MerkleProof`: leaves are used as is
+/// and each pair of nodes is sorted before being hashed.
+#[derive(Default)]
+pub struct SortedMerkleHasher<H: HashFunction>(MerkleHasher<H>);
+
+impl<H: HashFunction> Hasher for SortedMerkleHasher<H> {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes)
+    }
+}
+
+impl<H: HashFunction> Algorithm<Item> for SortedMerkleHasher<H> {
+    fn hash(&mut self) -> Item {
+        self.0.hash()
+    }
+
+    fn leaf(&mut self, leaf: Item) -> Item {
+        leaf
+    }
+
+    fn node(&mut self, left: Item, right: Item, _height: usize) -> Item {
+        let (first, second) = if left <= right {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        self.write(first.as_ref());
+        self.write(second.as_ref());
+        self.hash()
+    }
+
+    fn multi_node(&mut self, nodes: &[Item], height: usize) -> Item {
+        assert_eq!(nodes.len(), 2, "sorted pair hashing requires a binary tree");
+        self.node(nodes[0], nodes[1], height)
+    }
+}
+
+/// Tree hashing algorithm, along with the hash function used to namehash its
+/// leaves and the layout of the tree.
+pub trait TreeHasher: Algorithm<Item> {
+    type Function: HashFunction;
+    const MODE: HashMode;
+}
+
+impl<H: HashFunction> TreeHasher for MerkleHasher<H> {
+    type Function = H;
+    const MODE: HashMode = HashMode::Prefixed;
+}
+
+impl<H: HashFunction> TreeHasher for SortedMerkleHasher<H> {
+    type Function = H;
+    const MODE: HashMode = HashMode::SortedPair;
+}
+
+pub type Keccak256Hasher = MerkleHasher<Keccak256>;
+pub type SortedKeccak256Hasher = SortedMerkleHasher<Keccak256>;
+pub type Sha256Hasher = MerkleHasher<Sha256>;
+pub type SortedSha256Hasher = SortedMerkleHasher<Sha256>;
+
+/// Selects how the leaves and nodes of a tree are hashed and laid out.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum HashMode {
+    /// merkletree's default hashing ([`MerkleHasher`]), the leaves are padded
+    /// with the zero hash up to a power of 2
+    Prefixed,
+    /// OpenZeppelin's sorted pair hashing ([`SortedMerkleHasher`]) and tree
+    /// layout, the leaves are not padded
+    #[default]
+    SortedPair,
+}
+
+impl HashMode {
+    pub fn verify_proof<H: HashFunction>(
+        &self,
+        leaf: [u8; SIZE],
+        proof: &[[u8; SIZE]],
+        index: usize,
+        root: [u8; SIZE],
+    ) -> bool {
+        match self {
+            HashMode::Prefixed => verify_proof::<MerkleHasher<H>>(leaf, proof, index, root),
+            HashMode::SortedPair => verify_proof::<SortedMerkleHasher<H>>(leaf, proof, index, root),
+        }
+    }
+}
+
+/// Selects the hash function of a tree at runtime.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum HashKind {
+    #[default]
+    Keccak256,
+    Sha256,
+}
+
+impl FromStr for HashKind {
+    type Err = MerkleError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "keccak256" => Ok(HashKind::Keccak256),
+            "sha256" => Ok(HashKind::Sha256),
+            _ => Err(MerkleError::UnknownHashFunction(value.to_string())),
+        }
+    }
+}
+
+impl HashKind {
+    pub fn namehash(&self, domain: String) -> [u8; SIZE] {
+        match self {
+            HashKind::Keccak256 => get_namehash_with::<Keccak256>(domain),
+            HashKind::Sha256 => get_namehash_with::<Sha256>(domain),
+        }
+    }
+
+    pub fn verify_proof(
+        &self,
+        mode: HashMode,
+        leaf: [u8; SIZE],
+        proof: &[[u8; SIZE]],
+        index: usize,
+        root: [u8; SIZE],
+    ) -> bool {
+        match self {
+            HashKind::Keccak256 => mode.verify_proof::<Keccak256>(leaf, proof, index, root),
+            HashKind::Sha256 => mode.verify_proof::<Sha256>(leaf, proof, index, root),
+        }
     }
 }
 
@@ -82,28 +282,427 @@ pub fn generate_vector_of_elements<E: Element>(domains: Vec<[u8; SIZE]>) -> Vec<
 }
 
 pub fn keccak256(bytes: &[u8]) -> [u8; SIZE] {
-    let mut hasher = Keccak::v256();
-    hasher.update(bytes);
-    let mut output = [0u8; SIZE];
-    hasher.finalize(&mut output);
-    output
+    Keccak256::digest(bytes)
 }
 
+/// ENS namehash, computed with keccak256.
 pub fn get_namehash(domain: String) -> [u8; SIZE] {
+    get_namehash_with::<Keccak256>(domain)
+}
+
+pub fn get_namehash_with<H: HashFunction>(domain: String) -> [u8; SIZE] {
     domain.rsplit('.').fold([0u8; SIZE], |node, label| {
-        keccak256(&[node, keccak256(label.as_bytes())].concat())
+        H::digest(&[node, H::digest(label.as_bytes())].concat())
     })
 }
 
-pub fn get_merkle_tree<E: Element, A: Algorithm<E>, S: Store<E>, U: Unsigned>(leaves: Vec<String>) {
-    let hashes = leaves.into_iter().map(|x| get_namehash(x)).collect();
-    let elems: Vec<Item> = generate_vector_of_elements(hashes);
-    let tree: MerkleTree<E, A, S, U> = MerkleTree::new(elems).unwrap();
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub fn from_hex(value: &str) -> Result<[u8; SIZE], MerkleError> {
+    let mut output = [0u8; SIZE];
+    hex::decode_to_slice(value.trim_start_matches("0x"), &mut output)
+        .map_err(|_| MerkleError::InvalidHash(value.to_string()))?;
+    Ok(output)
+}
+
+/// Hashing of a tree, chosen when the tree is built and recorded along with its
+/// root so that its proofs can be checked.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub struct TreeHashing {
+    #[serde(default)]
+    pub mode: HashMode,
+    #[serde(default)]
+    pub function: HashKind,
+}
+
+impl TreeHashing {
+    pub fn sorted(function: HashKind) -> Self {
+        TreeHashing {
+            mode: HashMode::SortedPair,
+            function,
+        }
+    }
+
+    pub fn prefixed(function: HashKind) -> Self {
+        TreeHashing {
+            mode: HashMode::Prefixed,
+            function,
+        }
+    }
+
+    fn leaves(&self, domains: &[String]) -> Result<Vec<Item>, MerkleError> {
+        match (self.mode, self.function) {
+            (HashMode::Prefixed, HashKind::Keccak256) => get_leaves::<Keccak256Hasher>(domains),
+            (HashMode::Prefixed, HashKind::Sha256) => get_leaves::<Sha256Hasher>(domains),
+            (HashMode::SortedPair, HashKind::Keccak256) => {
+                get_leaves::<SortedKeccak256Hasher>(domains)
+            }
+            (HashMode::SortedPair, HashKind::Sha256) => get_leaves::<SortedSha256Hasher>(domains),
+        }
+    }
+
+    fn nodes(&self, leaves: Vec<Item>) -> Vec<u8> {
+        match (self.mode, self.function) {
+            (HashMode::Prefixed, HashKind::Keccak256) => get_nodes::<Keccak256Hasher>(leaves),
+            (HashMode::Prefixed, HashKind::Sha256) => get_nodes::<Sha256Hasher>(leaves),
+            (HashMode::SortedPair, HashKind::Keccak256) => {
+                get_nodes::<SortedKeccak256Hasher>(leaves)
+            }
+            (HashMode::SortedPair, HashKind::Sha256) => get_nodes::<SortedSha256Hasher>(leaves),
+        }
+    }
+
+    pub fn namehash(&self, domain: String) -> [u8; SIZE] {
+        self.function.namehash(domain)
+    }
+
+    pub fn verify_multiproof(&self, multiproof: &MultiProof, root: [u8; SIZE]) -> bool {
+        match (self.mode, self.function) {
+            (HashMode::Prefixed, _) => false,
+            (HashMode::SortedPair, HashKind::Keccak256) => {
+                verify_multiproof::<SortedKeccak256Hasher>(multiproof, root)
+            }
+            (HashMode::SortedPair, HashKind::Sha256) => {
+                verify_multiproof::<SortedSha256Hasher>(multiproof, root)
+            }
+        }
+    }
+}
+
+/// Binary merkle tree over the namehashes of a list of ENS domains. The nodes
+/// are stored in the heap layout of OpenZeppelin's merkle trees, where the root
+/// is at index 0 and the children of i are at 2i + 1 and 2i + 2. The domains
+/// are kept in leaf order so that a leaf can be found back from its domain.
+pub struct DomainTree<S: Store<Item>> {
+    nodes: S,
+    domains: Vec<String>,
+    // leaf index of each domain, built once with the tree
+    indices: HashMap<String, usize>,
+    hashing: TreeHashing,
+}
+
+impl<S: Store<Item>> DomainTree<S> {
+    fn new(nodes: S, domains: Vec<String>, hashing: TreeHashing) -> Self {
+        let mut indices = HashMap::with_capacity(domains.len());
+        for (index, domain) in domains.iter().enumerate() {
+            indices.entry(domain.clone()).or_insert(index);
+        }
+        DomainTree {
+            nodes,
+            domains,
+            indices,
+            hashing,
+        }
+    }
+
+    pub fn root(&self) -> [u8; SIZE] {
+        self.nodes.read_at(0).map(|x| x.0).unwrap_or_default()
+    }
+
+    /// Whether the tree was reopened from an existing store instead of built.
+    pub fn loaded_from_disk(&self) -> bool {
+        self.nodes.loaded_from_disk()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn hashing(&self) -> TreeHashing {
+        self.hashing
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    pub fn index_of(&self, domain: &str) -> Option<usize> {
+        self.indices.get(domain).copied()
+    }
+
+    // node of the leaf at `index`, OpenZeppelin stores the leaves in reverse
+    // order at the end of the tree
+    fn leaf_node(&self, index: usize) -> usize {
+        match self.hashing.mode {
+            HashMode::Prefixed => self.nodes.len() / 2 + index,
+            HashMode::SortedPair => self.nodes.len() - 1 - index,
+        }
+    }
+
+    fn node_at(&self, index: usize) -> Result<Item, MerkleError> {
+        self.nodes
+            .read_at(index)
+            .map_err(|e| MerkleError::InvalidTree(e.to_string()))
+    }
+
+    /// Returns the sibling hashes from the leaf of `domain` up to the root.
+    pub fn gen_proof(&self, domain: &str) -> Result<Vec<[u8; SIZE]>, MerkleError> {
+        let mut index = self
+            .index_of(domain)
+            .map(|x| self.leaf_node(x))
+            .ok_or_else(|| MerkleError::MissingLeaf(domain.to_string()))?;
+        let mut proof = vec![];
+        while index > 0 {
+            proof.push(self.node_at(sibling(index))?.0);
+            index = (index - 1) / 2;
+        }
+        Ok(proof)
+    }
+
+    pub fn get_proof_path(&self, domain: &str) -> Result<Vec<String>, MerkleError> {
+        Ok(self.gen_proof(domain)?.iter().map(|x| to_hex(x)).collect())
+    }
+
+    /// Generates an OpenZeppelin style multiproof for `domains`. The leaves
+    /// of the multiproof are ordered the way `MerkleProof.multiProofVerify`
+    /// expects them.
+    pub fn gen_multiproof(&self, domains: &[&str]) -> Result<MultiProof, MerkleError> {
+        if self.hashing.mode != HashMode::SortedPair {
+            return Err(MerkleError::UnsortedMultiProof());
+        }
+        let mut indices = domains
+            .iter()
+            .map(|x| {
+                self.index_of(x)
+                    .map(|i| self.leaf_node(i))
+                    .ok_or_else(|| MerkleError::MissingLeaf(x.to_string()))
+            })
+            .collect::<Result<Vec<usize>, MerkleError>>()?;
+        indices.sort_unstable_by(|a, b| b.cmp(a));
+        indices.dedup();
+
+        let mut stack: VecDeque<usize> = indices.iter().copied().collect();
+        let mut proof = Vec::new();
+        let mut proof_flags = Vec::new();
+        while let Some(&index) = stack.front() {
+            if index == 0 {
+                break;
+            }
+            stack.pop_front();
+            let sibling = sibling(index);
+            if stack.front() == Some(&sibling) {
+                proof_flags.push(true);
+                stack.pop_front();
+            } else {
+                proof_flags.push(false);
+                proof.push(self.node_at(sibling)?.0);
+            }
+            stack.push_back((index - 1) / 2);
+        }
+
+        Ok(MultiProof {
+            leaves: indices
+                .into_iter()
+                .map(|x| self.node_at(x).map(|x| x.0))
+                .collect::<Result<Vec<_>, MerkleError>>()?,
+            proof,
+            proof_flags,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MultiProof {
+    pub leaves: Vec<[u8; SIZE]>,
+    pub proof: Vec<[u8; SIZE]>,
+    pub proof_flags: Vec<bool>,
+}
+
+/// Rebuilds the root of a multiproof following OpenZeppelin's
+/// `processMultiProof`. The order of the pairs is not known, so this only
+/// makes sense with an algorithm sorting them like [`SortedKeccak256Hasher`].
+pub fn process_multiproof<A: Algorithm<Item>>(multiproof: &MultiProof) -> Option<[u8; SIZE]> {
+    let MultiProof {
+        leaves,
+        proof,
+        proof_flags,
+    } = multiproof;
+    if leaves.len() + proof.len() != proof_flags.len() + 1 {
+        return None;
+    }
+    let mut leaves = leaves.iter().map(|x| Item(*x));
+    let mut proof = proof.iter().map(|x| Item(*x));
+    let mut hashes: VecDeque<Item> = VecDeque::with_capacity(proof_flags.len());
+    let mut hasher = A::default();
+    for flag in proof_flags {
+        let a = leaves.next().or_else(|| hashes.pop_front())?;
+        let b = if *flag {
+            leaves.next().or_else(|| hashes.pop_front())?
+        } else {
+            proof.next()?
+        };
+        hasher.reset();
+        hashes.push_back(hasher.multi_node(&[a, b], 0));
+    }
+    match (hashes.pop_back(), multiproof.leaves.first()) {
+        (Some(root), _) => Some(root.0),
+        (None, Some(leaf)) => Some(*leaf),
+        (None, None) => multiproof.proof.first().copied(),
+    }
+}
+
+pub fn verify_multiproof<A: Algorithm<Item>>(multiproof: &MultiProof, root: [u8; SIZE]) -> bool {
+    process_multiproof::<A>(multiproof) == Some(root)
+}
+
+/// Tree over every ingredient of the catalog, hashed the same way as the recipe
+/// contracts verify proofs. It is stored on disk as the catalog can be large.
+pub type CatalogTree = DomainTree<DiskStore<Item>>;
+
+/// In memory tree over the ingredients of a single recipe, its root is the one
+/// the recipe contract is deployed with.
+pub type RecipeTree = DomainTree<VecStore<Item>>;
+
+const BRANCHES: usize = 2;
+
+// left children have odd indices in the heap layout
+fn sibling(index: usize) -> usize {
+    if index % 2 == 1 {
+        index + 1
+    } else {
+        index - 1
+    }
+}
+
+// the hashed leaves in the order of the last row of the tree. merkletree only
+// builds trees with a power of 2 number of leaves (at least 2), its leaves are
+// therefore padded with the zero hash, while OpenZeppelin reverses them
+fn get_leaves<A: TreeHasher>(domains: &[String]) -> Result<Vec<Item>, MerkleError> {
+    if domains.is_empty() {
+        return Err(MerkleError::EmptyTree());
+    }
+    let mut hashes: Vec<[u8; SIZE]> = domains
+        .iter()
+        .cloned()
+        .map(get_namehash_with::<A::Function>)
+        .collect();
+    match A::MODE {
+        HashMode::Prefixed => hashes.resize(next_pow2(hashes.len()).max(BRANCHES), [0u8; SIZE]),
+        HashMode::SortedPair => hashes.reverse(),
+    }
+    let mut hasher = A::default();
+    Ok(generate_vector_of_elements::<Item>(hashes)
+        .into_iter()
+        .map(|x| {
+            hasher.reset();
+            hasher.leaf(x)
+        })
+        .collect())
+}
+
+// hashes the nodes above the leaves, following OpenZeppelin's `makeMerkleTree`
+fn get_nodes<A: TreeHasher>(leaves: Vec<Item>) -> Vec<u8> {
+    let inner = leaves.len() - 1;
+    let mut nodes = vec![Item::default(); inner];
+    nodes.extend(leaves);
+    let mut hasher = A::default();
+    for i in (0..inner).rev() {
+        hasher.reset();
+        nodes[i] = hasher.node(nodes[2 * i + 1], nodes[2 * i + 2], 0);
+    }
+    nodes.iter().flat_map(|x| x.0).collect()
+}
+
+pub fn get_merkle_tree<S: Store<Item>>(
+    domains: Vec<String>,
+    hashing: TreeHashing,
+) -> Result<DomainTree<S>, MerkleError> {
+    let nodes = hashing.nodes(hashing.leaves(&domains)?);
+    let nodes = S::new_from_slice(nodes.len() / SIZE, &nodes)
+        .map_err(|e| MerkleError::InvalidTree(e.to_string()))?;
+    Ok(DomainTree::new(nodes, domains, hashing))
+}
+
+// written next to the store to know which leaves it was built from
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct StoreMetadata {
+    fingerprint: String,
+    leafs: usize,
+    // stores written before the hashing was recorded are rebuilt
+    hashing: TreeHashing,
+}
+
+fn get_metadata_path(config: &StoreConfig) -> PathBuf {
+    config.path.join(format!("{}-metadata.json", config.id))
+}
+
+/// Builds the tree in the store described by `config`. If the store already
+/// holds a tree built from the same domains it is reopened as is, otherwise
+/// it is rebuilt in place.
+pub fn get_merkle_tree_with_config<S: Store<Item>>(
+    domains: Vec<String>,
+    hashing: TreeHashing,
+    config: StoreConfig,
+) -> Result<DomainTree<S>, MerkleError> {
+    let elems = hashing.leaves(&domains)?;
+    let leaves: Vec<u8> = elems.iter().flat_map(|x| x.0).collect();
+    let metadata = StoreMetadata {
+        fingerprint: to_hex(&keccak256(&leaves)),
+        leafs: elems.len(),
+        hashing,
+    };
+    let metadata_path = get_metadata_path(&config);
+    let len = 2 * metadata.leafs - 1;
+
+    let stored: Option<StoreMetadata> = fs::read(&metadata_path)
+        .ok()
+        .and_then(|x| serde_json::from_slice(&x).ok());
+    if stored.as_ref() == Some(&metadata) {
+        if let Ok(nodes) = S::new_from_disk(len, BRANCHES, &config) {
+            return Ok(DomainTree::new(nodes, domains, hashing));
+        }
+    }
+
+    // the store is missing, stale or corrupted, it is rebuilt from scratch
+    let _ = fs::remove_file(&metadata_path);
+    let _ = S::delete(config.clone());
+    fs::create_dir_all(&config.path).map_err(|e| MerkleError::InvalidStore(e.to_string()))?;
+    let nodes = S::new_from_slice_with_config(len, BRANCHES, &hashing.nodes(elems), config)
+        .map_err(|e| MerkleError::InvalidTree(e.to_string()))?;
+    let metadata =
+        serde_json::to_vec(&metadata).map_err(|e| MerkleError::InvalidStore(e.to_string()))?;
+    fs::write(&metadata_path, metadata).map_err(|e| MerkleError::InvalidStore(e.to_string()))?;
+    Ok(DomainTree::new(nodes, domains, hashing))
+}
+
+/// Checks that `proof` leads from the namehash `leaf` to `root`. `index` is the
+/// position of the leaf in the tree and is only needed to order the pairs
+/// when `A` does not sort them.
+pub fn verify_proof<A: Algorithm<Item>>(
+    leaf: [u8; SIZE],
+    proof: &[[u8; SIZE]],
+    index: usize,
+    root: [u8; SIZE],
+) -> bool {
+    let mut hasher = A::default();
+    let mut node = hasher.leaf(Item(leaf));
+    let mut index = index;
+    for (height, sibling) in proof.iter().enumerate() {
+        hasher.reset();
+        node = if index & 1 == 0 {
+            hasher.multi_node(&[node, Item(*sibling)], height)
+        } else {
+            hasher.multi_node(&[Item(*sibling), node], height)
+        };
+        index >>= 1;
+    }
+    node.0 == root
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hex_literal::hex;
+
+    fn leaf(hash: [u8; SIZE]) -> [u8; SIZE] {
+        keccak256(&[&[0x00], hash.as_slice()].concat())
+    }
+
+    fn node(left: [u8; SIZE], right: [u8; SIZE]) -> [u8; SIZE] {
+        keccak256(&[&[0x01], left.as_slice(), right.as_slice()].concat())
+    }
 
     #[test]
     fn test_namehash_eth() {
@@ -120,4 +719,376 @@ mod tests {
             get_namehash(String::from("alice.eth"))
         );
     }
+
+    #[test]
+    fn test_from_hex() {
+        let hash = "0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e";
+        assert_eq!(hash, to_hex(&from_hex(hash).unwrap()));
+        assert!(from_hex("0x8574ea").is_err());
+        assert!(from_hex("hello").is_err());
+    }
+
+    #[test]
+    fn test_merkle_tree_root_two_domains() {
+        let tree: DomainTree<VecStore<Item>> = get_merkle_tree(
+            vec![String::from("alice.eth"), String::from("eth")],
+            TreeHashing::prefixed(HashKind::Keccak256),
+        )
+        .unwrap();
+        assert_eq!(
+            node(
+                leaf(hex!(
+                    "787192fc5378cc32aa956ddfdedbf26b24e8d78e40109add0eea2c1a012c3dec"
+                )),
+                leaf(hex!(
+                    "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+                )),
+            ),
+            tree.root()
+        );
+        assert_eq!(tree.leaf_count(), 2);
+    }
+
+    #[test]
+    fn test_merkle_tree_pads_leaves() {
+        let domains = vec![
+            String::from("abricot.eth"),
+            String::from("ail.eth"),
+            String::from("agaragar.eth"),
+        ];
+        let tree: DomainTree<VecStore<Item>> =
+            get_merkle_tree(domains.clone(), TreeHashing::prefixed(HashKind::Keccak256)).unwrap();
+        let leaves: Vec<[u8; SIZE]> = domains.into_iter().map(get_namehash).map(leaf).collect();
+        assert_eq!(
+            node(
+                node(leaves[0], leaves[1]),
+                node(leaves[2], leaf([0u8; SIZE]))
+            ),
+            tree.root()
+        );
+        assert_eq!(tree.leaf_count(), 3);
+        assert_eq!(tree.index_of("agaragar.eth"), Some(2));
+        assert_eq!(tree.index_of("hello.eth"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "EmptyTree")]
+    fn test_merkle_tree_without_leaves() {
+        get_merkle_tree::<VecStore<Item>>(vec![], TreeHashing::prefixed(HashKind::Keccak256))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_merkle_tree_gen_proof() {
+        let domains = vec![
+            String::from("abricot.eth"),
+            String::from("ail.eth"),
+            String::from("agaragar.eth"),
+        ];
+        let tree: DomainTree<VecStore<Item>> =
+            get_merkle_tree(domains.clone(), TreeHashing::prefixed(HashKind::Keccak256)).unwrap();
+        let leaves: Vec<[u8; SIZE]> = domains.into_iter().map(get_namehash).map(leaf).collect();
+        assert_eq!(
+            vec![leaf([0u8; SIZE]), node(leaves[0], leaves[1])],
+            tree.gen_proof("agaragar.eth").unwrap()
+        );
+        assert_eq!(
+            vec![
+                to_hex(&leaves[1]),
+                to_hex(&node(leaves[2], leaf([0u8; SIZE])))
+            ],
+            tree.get_proof_path("abricot.eth").unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "MissingLeaf")]
+    fn test_merkle_tree_gen_proof_missing_domain() {
+        let tree: DomainTree<VecStore<Item>> = get_merkle_tree(
+            vec![String::from("alice.eth"), String::from("eth")],
+            TreeHashing::prefixed(HashKind::Keccak256),
+        )
+        .unwrap();
+        tree.gen_proof("bob.eth").unwrap();
+    }
+
+    fn sorted_tree(domains: &[&str]) -> DomainTree<VecStore<Item>> {
+        get_merkle_tree(
+            domains.iter().map(|x| x.to_string()).collect(),
+            TreeHashing::default(),
+        )
+        .unwrap()
+    }
+
+    // roots and proofs computed with a port of `makeMerkleTree` from
+    // @openzeppelin/merkle-tree (keccak256 of the sorted pair, leaves in the
+    // given order as with `sortLeaves: false`) over the namehashes of the domains
+    #[test]
+    fn test_sorted_tree_openzeppelin_root() {
+        let tree = sorted_tree(&["alice.eth", "eth"]);
+        assert_eq!(
+            hex!("290a0e44add8cee6b85a1fb1c93abb766d7f509d1385ed1f580a888002593417"),
+            tree.root()
+        );
+        let tree = sorted_tree(&[
+            "abricot.eth",
+            "ail.eth",
+            "agaragar.eth",
+            "aiguillettedecanard.eth",
+        ]);
+        assert_eq!(
+            hex!("2a839312a0790f5b8b3be2836a0287b56b43d6771c4efe18ac1109211bd0addf"),
+            tree.root()
+        );
+        assert_eq!(
+            vec![
+                hex!("3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a"),
+                hex!("a59cc097dce99142620a5d71fe4a223b08d2a0d021e9a548ffdfdd2744b35dad"),
+            ],
+            tree.gen_proof("agaragar.eth").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sorted_tree_openzeppelin_odd_leaves() {
+        let tree = sorted_tree(&["abricot.eth", "ail.eth", "agaragar.eth"]);
+        assert_eq!(
+            hex!("c3be14ad3db3b532ba2f8ad893e98f222eb90888c573c825c419b2b8287493d3"),
+            tree.root()
+        );
+        assert_eq!(
+            vec![hex!(
+                "a59cc097dce99142620a5d71fe4a223b08d2a0d021e9a548ffdfdd2744b35dad"
+            )],
+            tree.gen_proof("agaragar.eth").unwrap()
+        );
+        assert_eq!(
+            vec![
+                hex!("659ede6c695c50ddd8eb948402f1d4164a77fc60d84b7e473eb9058b40444821"),
+                hex!("bb46ee301b409e685fdca2667a94deffe378f7081edb25cee0386dc0cd5c2aca"),
+            ],
+            tree.gen_proof("abricot.eth").unwrap()
+        );
+
+        let tree = sorted_tree(&[
+            "abricot.eth",
+            "ail.eth",
+            "agaragar.eth",
+            "aiguillettedecanard.eth",
+            "alice.eth",
+        ]);
+        assert_eq!(
+            hex!("e58f30922a22fc378e6829865831a2252b27e9b6c8abb80c2fc21a802730d8f5"),
+            tree.root()
+        );
+        assert_eq!(
+            vec![
+                hex!("3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a"),
+                hex!("0a4b1ec4bec1c2a67e433b63da21ee25cafd746e998f4c38d71883007c226e7b"),
+            ],
+            tree.gen_proof("agaragar.eth").unwrap()
+        );
+        let leaves: Vec<[u8; SIZE]> = tree.domains().iter().cloned().map(get_namehash).collect();
+        assert_eq!(
+            MultiProof {
+                leaves: vec![leaves[0], leaves[2]],
+                proof: vec![leaves[1], leaves[3], leaves[4]],
+                proof_flags: vec![false, false, false, true],
+            },
+            tree.gen_multiproof(&["abricot.eth", "agaragar.eth"])
+                .unwrap()
+        );
+
+        let tree = sorted_tree(&["alice.eth"]);
+        assert_eq!(get_namehash(String::from("alice.eth")), tree.root());
+        assert!(tree.gen_proof("alice.eth").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_proof_sorted_pair() {
+        let tree = sorted_tree(&[
+            "abricot.eth",
+            "ail.eth",
+            "agaragar.eth",
+            "aiguillettedecanard.eth",
+        ]);
+        let leaf = get_namehash(String::from("agaragar.eth"));
+        let proof = tree.gen_proof("agaragar.eth").unwrap();
+        // the index is not needed to verify sorted pairs
+        assert!(HashMode::SortedPair.verify_proof::<Keccak256>(leaf, &proof, 0, tree.root()));
+        assert!(!HashMode::Prefixed.verify_proof::<Keccak256>(leaf, &proof, 2, tree.root()));
+        assert!(!HashMode::SortedPair.verify_proof::<Keccak256>(
+            get_namehash(String::from("ail.eth")),
+            &proof,
+            0,
+            tree.root()
+        ));
+    }
+
+    #[test]
+    fn test_verify_proof_prefixed() {
+        let domains = vec![
+            String::from("abricot.eth"),
+            String::from("ail.eth"),
+            String::from("agaragar.eth"),
+        ];
+        let tree: DomainTree<VecStore<Item>> =
+            get_merkle_tree(domains, TreeHashing::prefixed(HashKind::Keccak256)).unwrap();
+        let leaf = get_namehash(String::from("agaragar.eth"));
+        let proof = tree.gen_proof("agaragar.eth").unwrap();
+        assert!(HashMode::Prefixed.verify_proof::<Keccak256>(leaf, &proof, 2, tree.root()));
+        assert!(!HashMode::Prefixed.verify_proof::<Keccak256>(leaf, &proof, 3, tree.root()));
+    }
+
+    #[test]
+    fn test_gen_multiproof() {
+        let tree = sorted_tree(&[
+            "abricot.eth",
+            "ail.eth",
+            "agaragar.eth",
+            "aiguillettedecanard.eth",
+        ]);
+        let leaves: Vec<[u8; SIZE]> = tree.domains().iter().cloned().map(get_namehash).collect();
+        let multiproof = tree
+            .gen_multiproof(&["abricot.eth", "agaragar.eth"])
+            .unwrap();
+        assert_eq!(
+            MultiProof {
+                leaves: vec![leaves[0], leaves[2]],
+                proof: vec![leaves[1], leaves[3]],
+                proof_flags: vec![false, false, true],
+            },
+            multiproof
+        );
+        assert!(verify_multiproof::<SortedKeccak256Hasher>(
+            &multiproof,
+            tree.root()
+        ));
+    }
+
+    #[test]
+    fn test_verify_multiproof() {
+        let tree = sorted_tree(&[
+            "abricot.eth",
+            "ail.eth",
+            "agaragar.eth",
+            "aiguillettedecanard.eth",
+            "alice.eth",
+        ]);
+        for domains in [
+            vec!["alice.eth"],
+            vec!["ail.eth", "abricot.eth"],
+            vec!["alice.eth", "ail.eth", "aiguillettedecanard.eth"],
+            tree.domains().iter().map(|x| x.as_str()).collect(),
+        ] {
+            let multiproof = tree.gen_multiproof(&domains).unwrap();
+            assert_eq!(multiproof.leaves.len(), domains.len());
+            assert!(verify_multiproof::<SortedKeccak256Hasher>(
+                &multiproof,
+                tree.root()
+            ));
+        }
+
+        let mut multiproof = tree.gen_multiproof(&["ail.eth", "alice.eth"]).unwrap();
+        multiproof.leaves.swap(0, 1);
+        assert!(!verify_multiproof::<SortedKeccak256Hasher>(
+            &multiproof,
+            tree.root()
+        ));
+        multiproof.proof.pop();
+        assert!(process_multiproof::<SortedKeccak256Hasher>(&multiproof).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "MissingLeaf")]
+    fn test_gen_multiproof_missing_domain() {
+        let tree = sorted_tree(&["alice.eth", "eth"]);
+        tree.gen_multiproof(&["alice.eth", "bob.eth"]).unwrap();
+    }
+
+    #[test]
+    fn test_merkle_tree_with_config_reopens_store() {
+        let dir = std::env::temp_dir().join(format!("lfb-merkle-{}", std::process::id()));
+        let config = StoreConfig::new(&dir, "catalog", 0);
+        let domains: Vec<String> = ["abricot.eth", "ail.eth", "agaragar.eth"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        let root = sorted_tree(&["abricot.eth", "ail.eth", "agaragar.eth"]).root();
+
+        let tree: CatalogTree =
+            get_merkle_tree_with_config(domains.clone(), TreeHashing::default(), config.clone())
+                .unwrap();
+        assert!(!tree.loaded_from_disk());
+        assert_eq!(root, tree.root());
+        drop(tree);
+
+        let tree: CatalogTree =
+            get_merkle_tree_with_config(domains.clone(), TreeHashing::default(), config.clone())
+                .unwrap();
+        assert!(tree.loaded_from_disk());
+        assert_eq!(root, tree.root());
+        assert_eq!(
+            sorted_tree(&["abricot.eth", "ail.eth", "agaragar.eth"])
+                .gen_proof("ail.eth")
+                .unwrap(),
+            tree.gen_proof("ail.eth").unwrap()
+        );
+        drop(tree);
+
+        let mut domains = domains;
+        domains.push(String::from("aiguillettedecanard.eth"));
+        let tree: CatalogTree =
+            get_merkle_tree_with_config(domains, TreeHashing::default(), config).unwrap();
+        assert!(!tree.loaded_from_disk());
+        assert_eq!(
+            hex!("2a839312a0790f5b8b3be2836a0287b56b43d6771c4efe18ac1109211bd0addf"),
+            tree.root()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_namehash_sha256() {
+        assert_eq!(
+            hex!("03841b26168e5b1afbc50a048628f31aa3aec4f96cd244d171df3915e789bc97"),
+            HashKind::Sha256.namehash(String::from("alice.eth"))
+        );
+        assert_eq!(
+            get_namehash(String::from("alice.eth")),
+            HashKind::Keccak256.namehash(String::from("alice.eth"))
+        );
+    }
+
+    #[test]
+    fn test_sorted_sha256_tree() {
+        let sha256 = TreeHashing::sorted(HashKind::Sha256);
+        let tree: DomainTree<VecStore<Item>> =
+            get_merkle_tree(vec![String::from("alice.eth"), String::from("eth")], sha256).unwrap();
+        assert_eq!(
+            hex!("556d1785d4bf7f919c2a724434f7036062510e2bde2235196c109ad18871695e"),
+            tree.root()
+        );
+
+        let domains = vec![
+            String::from("abricot.eth"),
+            String::from("ail.eth"),
+            String::from("agaragar.eth"),
+        ];
+        let tree: DomainTree<VecStore<Item>> = get_merkle_tree(domains, sha256).unwrap();
+        assert_eq!(
+            hex!("2e992b2f7fbaf68c782a362b94f8d7d11c3f406bd2d7d56eef06a4873c37cd6e"),
+            tree.root()
+        );
+        let proof = tree.gen_proof("ail.eth").unwrap();
+        let leaf = HashKind::Sha256.namehash(String::from("ail.eth"));
+        assert!(HashKind::Sha256.verify_proof(HashMode::SortedPair, leaf, &proof, 1, tree.root()));
+        assert!(!HashKind::Keccak256.verify_proof(
+            HashMode::SortedPair,
+            leaf,
+            &proof,
+            1,
+            tree.root()
+        ));
+    }
 }
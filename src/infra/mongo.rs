@@ -1,5 +1,17 @@
 mod api;
 pub use api::*;
 
+mod memory;
+pub use memory::MemoryRep;
+
+mod repository;
+pub use repository::Repository;
+
+#[cfg(test)]
+mod suite;
+
 mod types;
-pub use types::{Ingredient, Recipe};
+pub use types::{
+    Block, BlockChange, CatalogDiff, CatalogVersion, Completion, Deadline, HistoryEvent,
+    HistoryEventKind, Ingredient, LeafProof, Recipe, Status, MAINNET_CHAIN_ID,
+};
@@ -1,11 +1,27 @@
-use super::types::{Ingredient, Recipe, Status};
+use super::repository::{domains_hash, get_recipe_proofs, parse_ingredient_ids, Repository};
+use super::types::{
+    Block, BlockChange, CatalogVersion, Completion, Deadline, HistoryEvent, Ingredient, Recipe,
+    Status, MAINNET_CHAIN_ID,
+};
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::{
+    get_merkle_tree, get_merkle_tree_with_config, CatalogTree, MerkleError, TreeHashing,
+};
+use merkletree::store::StoreConfig;
 use mongodb::{
-    bson::{doc, oid::ObjectId},
+    bson::{doc, from_document, oid::ObjectId, to_bson, to_document, Bson, Document},
     error::Error as mongoError,
-    options::{FindOptions, UpdateOptions},
-    sync::Client,
+    options::{FindOneOptions, FindOptions, IndexOptions, ReplaceOptions, UpdateOptions},
+    Client, Collection, IndexModel,
 };
-use std::str::FromStr;
+use rocket::async_trait;
+use rocket::futures::TryStreamExt;
+use rocket::tokio::{sync::Mutex as AsyncMutex, task};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,6 +30,8 @@ pub enum MongoRepError {
     QueryError(#[from] mongoError),
     #[error("missing ingredient {0}")]
     InvalidIngredientName(String),
+    #[error("malformed ingredient id {0}")]
+    InvalidIngredientId(String),
     #[error("recipe not found for ingredients")]
     InvalidIngredientsList(),
     #[error("incorrect ingredients list {0}, expected between 2 and 6 ingredients")]
@@ -28,28 +46,160 @@ pub enum MongoRepError {
     InvalidAddRecipe(),
     #[error("invalid update for recipe at {0}")]
     InvalidUpdate(String),
+    #[error("could not compute merkle tree")]
+    InvalidMerkleTree(#[from] MerkleError),
+    #[error("missing catalog version {0}")]
+    InvalidCatalogVersion(i64),
+    #[error("could not add catalog version to database")]
+    InvalidAddCatalogVersion(),
+    #[error("could not add block {0} to database")]
+    InvalidAddBlock(i64),
+    #[error("could not roll back block {0}")]
+    InvalidRollback(i64),
+    #[error("could not add event to the history of recipe {0}")]
+    InvalidAddHistoryEvent(String),
+    #[error("recipe {0} can not go from {1:?} to {2:?}")]
+    InvalidTransition(String, Status, Status),
+    #[error("block {0} is not finalized yet")]
+    BlockNotFinalized(i64),
+}
+
+// ingredient paths written by a single update command
+const PATH_UPDATE_BATCH: usize = 1000;
+
+// catalog tree with the domains and the hashing it was built from
+struct CachedCatalogTree {
+    domains_hash: String,
+    hashing: TreeHashing,
+    tree: Arc<CatalogTree>,
 }
 
+// collections are handles on the same client, so a clone can be handed to a
+// background task
+#[derive(Clone)]
 pub struct MongoRep {
-    pub ingredients: mongodb::sync::Collection<Ingredient>,
-    pub recipes: mongodb::sync::Collection<Recipe>,
+    pub ingredients: mongodb::Collection<Ingredient>,
+    pub recipes: mongodb::Collection<Recipe>,
+    pub merkle_roots: mongodb::Collection<CatalogVersion>,
+    pub blocks: mongodb::Collection<Block>,
+    pub recipe_events: mongodb::Collection<HistoryEvent>,
+    // confirmations of the indexed chains, recorded by their indexer
+    pub chains: mongodb::Collection<Document>,
+    // runs the commands the collections have no method for
+    database: mongodb::Database,
+    // directory where the catalog tree is persisted, kept in a temporary file
+    // when missing
+    pub merkle_dir: Option<PathBuf>,
+    pub catalog_hashing: TreeHashing,
+    // latest catalog tree, shared by every clone of the repository; its lock
+    // also keeps a single build writing to the persisted tree at a time
+    catalog_tree: Arc<AsyncMutex<Option<CachedCatalogTree>>>,
+    // chains whose recipe trees are not hashed with the default hashing,
+    // registered by their indexer
+    recipe_hashing: Arc<Mutex<HashMap<i64, TreeHashing>>>,
 }
 
 impl MongoRep {
-    pub fn init(uri: String, database: &str) -> Result<Self, MongoRepError> {
-        let client = Client::with_uri_str(uri)?;
+    pub async fn init(uri: String, database: &str) -> Result<Self, MongoRepError> {
+        let client = Client::with_uri_str(uri).await?;
         let database = client.database(database);
         let rep = MongoRep {
             ingredients: database.collection("ingredients"),
             recipes: database.collection("recipes"),
+            merkle_roots: database.collection("merkle_roots"),
+            blocks: database.collection("blocks"),
+            recipe_events: database.collection("recipe_events"),
+            chains: database.collection("chains"),
+            database: database.clone(),
+            merkle_dir: None,
+            catalog_hashing: TreeHashing::default(),
+            catalog_tree: Arc::default(),
+            recipe_hashing: Arc::default(),
         };
-        return Ok(rep);
+        Ok(rep)
+    }
+
+    pub fn with_merkle_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.merkle_dir = Some(dir.into());
+        self
+    }
+
+    pub fn with_catalog_hashing(mut self, hashing: TreeHashing) -> Self {
+        self.catalog_hashing = hashing;
+        self
+    }
+
+    // only rebuilds the persisted tree when the catalog changed
+    fn build_catalog_tree(&self, domains: Vec<String>) -> Result<CatalogTree, MongoRepError> {
+        match &self.merkle_dir {
+            Some(dir) => {
+                let config = StoreConfig::new(dir, "catalog", 0);
+                Ok(get_merkle_tree_with_config(
+                    domains,
+                    self.catalog_hashing,
+                    config,
+                )?)
+            }
+            None => Ok(get_merkle_tree(domains, self.catalog_hashing)?),
+        }
+    }
+
+    /// Creates the indexes the repository relies on, a catalog version can only
+    /// be recorded once.
+    pub async fn create_indexes(&self) -> Result<(), MongoRepError> {
+        let index = IndexModel::builder()
+            .keys(doc! {"version": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.merkle_roots.create_index(index, None).await?;
+        Ok(())
     }
 
-    pub fn get_ingredient(&self, name: &str) -> Result<Ingredient, MongoRepError> {
+    /// Puts the documents written before the chain was recorded on mainnet,
+    /// returns the number of updated documents.
+    pub async fn set_default_chain_id(&self) -> Result<u64, MongoRepError> {
+        let filter = doc! {"chain_id": {"$exists": false}};
+        let update = doc! {"$set": {"chain_id": MAINNET_CHAIN_ID}};
+        let recipes = self
+            .recipes
+            .update_many(filter.clone(), update.clone(), None)
+            .await?;
+        let events = self
+            .recipe_events
+            .update_many(filter.clone(), update.clone(), None)
+            .await?;
+        let blocks = self.blocks.update_many(filter, update, None).await?;
+        Ok(recipes.modified_count + events.modified_count + blocks.modified_count)
+    }
+
+    /// Rewrites the documents stored before hashes and addresses were typed,
+    /// with checksummed addresses and null owners instead of empty strings.
+    /// The domains stored in full by the first catalog versions are dropped.
+    /// Returns the number of updated documents.
+    pub async fn normalize_documents(&self) -> Result<u64, MongoRepError> {
+        let versions = self
+            .merkle_roots
+            .update_many(
+                doc! {"domains": {"$exists": true}},
+                doc! {"$unset": {"domains": ""}},
+                None,
+            )
+            .await?;
+        Ok(versions.modified_count
+            + normalize_collection(&self.ingredients).await?
+            + normalize_collection(&self.recipes).await?
+            + normalize_collection(&self.recipe_events).await?
+            + normalize_collection(&self.blocks).await?)
+    }
+}
+
+#[async_trait]
+impl Repository for MongoRep {
+    async fn get_ingredient(&self, name: &str) -> Result<Ingredient, MongoRepError> {
         match self
             .ingredients
             .find_one(doc! {"domain": &name}, None)
+            .await
             .map_err(MongoRepError::from)?
         {
             Some(ing) => Ok(ing),
@@ -57,101 +207,383 @@ impl MongoRep {
         }
     }
 
-    pub fn get_ingredients(
+    async fn get_ingredients(
         &self,
         ingredients: Vec<&str>,
     ) -> Result<Vec<Ingredient>, MongoRepError> {
         let cursor = self
             .ingredients
             .find(doc! {"domain": {"$in": ingredients}}, None)
+            .await
             .map_err(MongoRepError::from)?;
-        match cursor.collect::<Result<Vec<Ingredient>, mongoError>>() {
-            Ok(v) if v.len() > 0 => Ok(v),
+        match cursor.try_collect::<Vec<Ingredient>>().await {
+            Ok(v) if !v.is_empty() => Ok(v),
             Ok(_) => Err(MongoRepError::EmptyResponse()),
             _ => Err(MongoRepError::InvalidIngredientsList()),
         }
     }
 
-    pub fn get_ingredients_by_hash(
+    async fn get_all_ingredients(&self) -> Result<Vec<Ingredient>, MongoRepError> {
+        let find_options = FindOptions::builder().sort(doc! {"domain": 1}).build();
+        let cursor = self
+            .ingredients
+            .find(doc! {}, find_options)
+            .await
+            .map_err(MongoRepError::from)?;
+        cursor
+            .try_collect::<Vec<Ingredient>>()
+            .await
+            .map_err(MongoRepError::from)
+    }
+
+    async fn get_catalog_tree(&self) -> Result<Arc<CatalogTree>, MongoRepError> {
+        let domains: Vec<String> = self
+            .get_all_ingredients()
+            .await?
+            .into_iter()
+            .map(|x| x.domain)
+            .collect();
+        let hash = domains_hash(&domains);
+        let mut cached = self.catalog_tree.lock().await;
+        if let Some(cached) = cached.as_ref() {
+            if cached.domains_hash == hash && cached.hashing == self.catalog_hashing {
+                return Ok(cached.tree.clone());
+            }
+        }
+        // hashing the catalog takes a while, keep it off the async workers
+        let rep = self.clone();
+        let tree = task::spawn_blocking(move || rep.build_catalog_tree(domains))
+            .await
+            .map_err(|e| MerkleError::InvalidTree(e.to_string()))??;
+        let tree = Arc::new(tree);
+        *cached = Some(CachedCatalogTree {
+            domains_hash: hash,
+            hashing: self.catalog_hashing,
+            tree: tree.clone(),
+        });
+        Ok(tree)
+    }
+
+    fn catalog_hashing(&self) -> TreeHashing {
+        self.catalog_hashing
+    }
+
+    fn recipe_hashing(&self, chain_id: i64) -> TreeHashing {
+        self.recipe_hashing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&chain_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_recipe_hashing(&self, chain_id: i64, hashing: TreeHashing) {
+        self.recipe_hashing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(chain_id, hashing);
+    }
+
+    async fn update_ingredients_path(&self) -> Result<usize, MongoRepError> {
+        let ingredients = self.get_all_ingredients().await?;
+        if ingredients.is_empty() {
+            return Ok(0);
+        }
+        let tree = self.get_catalog_tree().await?;
+        let updates = ingredients
+            .iter()
+            .map(|x| {
+                let path = tree.get_proof_path(&x.domain)?;
+                Ok(doc! {"q": {"domain": &x.domain}, "u": {"$set": {"path": path}}})
+            })
+            .collect::<Result<Vec<Document>, MongoRepError>>()?;
+        // a single update command per batch, kept under the size limit of a command
+        for batch in updates.chunks(PATH_UPDATE_BATCH) {
+            let result = self
+                .database
+                .run_command(
+                    doc! {"update": self.ingredients.name(), "updates": batch, "ordered": false},
+                    None,
+                )
+                .await?;
+            if let Ok(errors) = result.get_array("writeErrors") {
+                let domain = errors
+                    .first()
+                    .and_then(|x| x.as_document())
+                    .and_then(|x| x.get_i32("index").ok())
+                    .and_then(|x| batch.get(x as usize))
+                    .and_then(|x| x.get_document("q").ok())
+                    .and_then(|x| x.get_str("domain").ok())
+                    .unwrap_or_default();
+                return Err(MongoRepError::InvalidAddIngredient(domain.to_string()));
+            }
+        }
+        Ok(ingredients.len())
+    }
+
+    async fn get_catalog_version_latest(&self) -> Result<Option<CatalogVersion>, MongoRepError> {
+        let find_options = FindOneOptions::builder().sort(doc! {"version": -1}).build();
+        self.merkle_roots
+            .find_one(doc! {}, find_options)
+            .await
+            .map_err(MongoRepError::from)
+    }
+
+    async fn get_catalog_version(&self, version: i64) -> Result<CatalogVersion, MongoRepError> {
+        match self
+            .merkle_roots
+            .find_one(doc! {"version": version}, None)
+            .await
+            .map_err(MongoRepError::from)?
+        {
+            Some(v) => Ok(v),
+            None => Err(MongoRepError::InvalidCatalogVersion(version)),
+        }
+    }
+
+    async fn get_catalog_versions(&self) -> Result<Vec<CatalogVersion>, MongoRepError> {
+        let find_options = FindOptions::builder().sort(doc! {"version": 1}).build();
+        let cursor = self
+            .merkle_roots
+            .find(doc! {}, find_options)
+            .await
+            .map_err(MongoRepError::from)?;
+        cursor
+            .try_collect::<Vec<CatalogVersion>>()
+            .await
+            .map_err(MongoRepError::from)
+    }
+
+    async fn get_ingredients_by_hash(
         &self,
-        hashes: Vec<&str>,
+        hashes: &[H256],
     ) -> Result<Vec<Ingredient>, MongoRepError> {
         let cursor = self
             .ingredients
-            .find(doc! {"hash": {"$in" : hashes}}, None)
+            .find(doc! {"hash": {"$in" : hashes.to_vec()}}, None)
+            .await
             .map_err(MongoRepError::from)?;
-        match cursor.collect::<Result<Vec<Ingredient>, mongoError>>() {
+        match cursor.try_collect::<Vec<Ingredient>>().await {
             Ok(v) => Ok(v),
-            Err(e) => Err(MongoRepError::InvalidIngredientHash()),
+            Err(_) => Err(MongoRepError::InvalidIngredientHash()),
         }
     }
 
-    pub fn get_ingredients_by_id(&self, ids: Vec<&str>) -> Result<Vec<Ingredient>, MongoRepError> {
-        let ids: Vec<ObjectId> = ids
-            .into_iter()
-            .map(|x| ObjectId::from_str(x).unwrap())
-            .collect::<Vec<ObjectId>>();
+    async fn update_ingredient_owner(
+        &self,
+        hash: &H256,
+        owner: Option<&Address>,
+        expiry: Option<i64>,
+    ) -> Result<bool, MongoRepError> {
+        let mut update = doc! {};
+        if let Some(owner) = owner {
+            update.insert("owner", owner);
+        }
+        if let Some(expiry) = expiry {
+            update.insert("expiry", expiry);
+        }
+        if update.is_empty() {
+            return Ok(false);
+        }
+        match self
+            .ingredients
+            .update_one(doc! {"hash": hash}, doc! {"$set": update}, None)
+            .await
+        {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(_) => Err(MongoRepError::InvalidIngredientHash()),
+        }
+    }
+
+    async fn restore_ingredient_owner(
+        &self,
+        hash: &H256,
+        owner: Option<&Address>,
+        expiry: i64,
+    ) -> Result<bool, MongoRepError> {
+        let owner = owner.map_or(Bson::Null, Bson::from);
+        match self
+            .ingredients
+            .update_one(
+                doc! {"hash": hash},
+                doc! {"$set": {"owner": owner, "expiry": expiry}},
+                None,
+            )
+            .await
+        {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(_) => Err(MongoRepError::InvalidIngredientHash()),
+        }
+    }
+
+    async fn get_ingredients_by_owner(
+        &self,
+        owner: &Address,
+    ) -> Result<Vec<Ingredient>, MongoRepError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs() as i64);
+        let cursor = self
+            .ingredients
+            .find(
+                doc! {"owner": owner, "$or": [{"expiry": null}, {"expiry": 0}, {"expiry": {"$gt": now}}]},
+                None,
+            ).await
+            .map_err(MongoRepError::from)?;
+        cursor
+            .try_collect::<Vec<Ingredient>>()
+            .await
+            .map_err(MongoRepError::from)
+    }
+
+    async fn get_ingredients_by_id(
+        &self,
+        ids: Vec<&str>,
+    ) -> Result<Vec<Ingredient>, MongoRepError> {
+        let ids = parse_ingredient_ids(ids)?;
 
         let cursor = self
             .ingredients
             .find(doc! {"_id": {"$in" : ids}}, None)
+            .await
             .map_err(MongoRepError::from)?;
-        match cursor.collect::<Result<Vec<Ingredient>, mongoError>>() {
+        match cursor.try_collect::<Vec<Ingredient>>().await {
             Ok(v) => Ok(v),
-            Err(e) => Err(MongoRepError::InvalidIngredientHash()),
+            Err(_) => Err(MongoRepError::InvalidIngredientHash()),
         }
     }
 
-    pub fn get_recipes(&self, ingredients: Vec<&str>) -> Result<Vec<Recipe>, MongoRepError> {
+    async fn get_recipes(
+        &self,
+        ingredients: Vec<&str>,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<Recipe>, MongoRepError> {
         let len = ingredients.len();
-        if len >= 6 || len < 2 {
+        if !(2..6).contains(&len) {
             return Err(MongoRepError::IncorrectIngredientsLength(len));
         }
-        let ingredients = self.get_ingredients(ingredients)?;
+        let ingredients = self.get_ingredients(ingredients).await?;
         let ids: Vec<mongodb::bson::Document> = ingredients
             .into_iter()
             //TODO improve the handling of None
-            .map(|x| x.id.unwrap_or_else(|| ObjectId::new()))
+            .map(|x| x.id.unwrap_or_else(ObjectId::new))
             .map(|x| doc! { "$elemMatch": {"id": x} })
             .collect();
         let cursor = self
             .recipes
-            .find(doc! {"ingredients": {"$all": ids}}, None)
+            .find(
+                with_chain(doc! {"ingredients": {"$all": ids}}, chain_id),
+                None,
+            )
+            .await
             .map_err(MongoRepError::from)?;
-        match cursor.collect::<Result<Vec<Recipe>, mongoError>>() {
+        match cursor.try_collect::<Vec<Recipe>>().await {
             Ok(v) => Ok(v),
-            Err(e) => Err(MongoRepError::InvalidIngredientsList()),
+            Err(_) => Err(MongoRepError::InvalidIngredientsList()),
+        }
+    }
+
+    async fn get_recipes_at(
+        &self,
+        chain_id: i64,
+        ingredients: Vec<&str>,
+        block: i64,
+    ) -> Result<Vec<Recipe>, MongoRepError> {
+        let len = ingredients.len();
+        if !(2..6).contains(&len) {
+            return Err(MongoRepError::IncorrectIngredientsLength(len));
+        }
+        let hashes: Vec<H256> = self
+            .get_ingredients(ingredients)
+            .await?
+            .into_iter()
+            .map(|x| x.hash)
+            .collect();
+        let cursor = self
+            .recipe_events
+            .find(
+                doc! {"chain_id": chain_id, "event": "RecipeCreated", "block": {"$lte": block}, "ingredients": {"$all": hashes}},
+                None,
+            ).await
+            .map_err(MongoRepError::from)?;
+        let created = cursor
+            .try_collect::<Vec<HistoryEvent>>()
+            .await
+            .map_err(|_| MongoRepError::InvalidIngredientsList())?;
+        let mut recipes = vec![];
+        for event in created {
+            if let Some(recipe) = self.get_recipe_at(chain_id, &event.address, block).await? {
+                recipes.push(recipe);
+            }
         }
+        Ok(recipes)
     }
 
-    pub fn get_recipe(&self, address: &str) -> Result<Recipe, MongoRepError> {
-        match self.recipes.find_one(doc! {"address": address}, None) {
-            Ok(v) => Ok(v.unwrap()),
+    async fn get_recipe(&self, chain_id: i64, address: &Address) -> Result<Recipe, MongoRepError> {
+        match self
+            .recipes
+            .find_one(doc! {"chain_id": chain_id, "address": address}, None)
+            .await
+        {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => Err(MongoRepError::EmptyResponse()),
             Err(e) => Err(MongoRepError::QueryError(e)),
         }
     }
 
-    pub fn get_recipes_ongoing(&self) -> Result<Vec<Recipe>, MongoRepError> {
+    async fn get_recipes_ongoing(
+        &self,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<Recipe>, MongoRepError> {
         let cursor = self
             .recipes
-            .find(doc! {"status": "Ongoing"}, None)
+            .find(with_chain(doc! {"status": "Ongoing"}, chain_id), None)
+            .await
             .map_err(MongoRepError::from)?;
-        match cursor.collect::<Result<Vec<Recipe>, mongoError>>() {
+        match cursor.try_collect::<Vec<Recipe>>().await {
             Ok(v) => Ok(v),
             Err(e) => Err(MongoRepError::QueryError(e)),
         }
     }
 
-    pub fn add_recipe(
+    async fn get_recipe_addresses(&self, chain_id: i64) -> Result<Vec<Address>, MongoRepError> {
+        let addresses = self
+            .recipes
+            .distinct("address", doc! {"chain_id": chain_id}, None)
+            .await?;
+        Ok(addresses
+            .iter()
+            .filter_map(|x| x.as_str().and_then(|x| x.parse().ok()))
+            .collect())
+    }
+
+    async fn add_recipe(
         &self,
-        address: &str,
-        hashes: Vec<&str>,
+        chain_id: i64,
+        address: &Address,
+        hashes: &[H256],
         block: i64,
+        version: &str,
+        deadline: &Deadline,
     ) -> Result<bool, MongoRepError> {
-        let ingredients = self.get_ingredients_by_hash(hashes).unwrap_or_default();
+        let mut ingredients = self.get_ingredients_by_hash(hashes).await?;
+        // a recipe missing some of its ingredients could never be completed
+        if hashes
+            .iter()
+            .any(|hash| !ingredients.iter().any(|x| x.hash == *hash))
+        {
+            return Ok(false);
+        }
+        // the leaves of the recipe tree follow the order of the contract
+        ingredients.sort_by_key(|x| hashes.iter().position(|h| *h == x.hash));
+        let hashing = self.recipe_hashing(chain_id);
+        let (root, proofs) = get_recipe_proofs(&ingredients, hashing)?;
+        let proofs = to_bson(&proofs).map_err(|_| MongoRepError::InvalidAddRecipe())?;
+        let deadline = to_bson(deadline).map_err(|_| MongoRepError::InvalidAddRecipe())?;
+        let hashing = to_bson(&hashing).map_err(|_| MongoRepError::InvalidAddRecipe())?;
         let ingredients: Vec<mongodb::bson::Document> = ingredients
             .iter()
-            .map(|x| doc! {"id": x.id.unwrap(), "status": "Ongoing", "owner": ""})
+            .map(|x| doc! {"id": x.id.unwrap(), "status": "Ongoing", "owner": Bson::Null})
             .collect();
 
         let mut option = UpdateOptions::default();
@@ -159,10 +591,10 @@ impl MongoRep {
         match self
             .recipes
             .update_one(
-                doc! {"address": address.to_string()},
-                doc! {"$setOnInsert": {"address": address.to_string(), "status": "Ongoing", "ingredients": ingredients, "last_block": block}},
+                doc! {"chain_id": chain_id, "address": address.to_string()},
+                doc! {"$setOnInsert": {"chain_id": chain_id, "address": address.to_string(), "status": "Ongoing", "ingredients": ingredients, "last_block": block, "root": root, "proofs": proofs, "version": version, "deadline": deadline, "hashing": hashing}},
                 option,
-            )
+            ).await
             .map_err(MongoRepError::from)
         {
             Ok(_) => Ok(true),
@@ -170,89 +602,274 @@ impl MongoRep {
         }
     }
 
-    pub fn update_recipe(
+    async fn update_recipe(
         &self,
-        address: &str,
-        hash: &str,
-        owner: &str,
-        block: i64,
+        chain_id: i64,
+        address: &Address,
+        hash: &H256,
+        completion: &Completion,
     ) -> Result<bool, MongoRepError> {
-        let ingredient = &self.get_ingredients_by_hash(vec![hash])?[0];
+        let ingredients = self.get_ingredients_by_hash(&[*hash]).await?;
+        let ingredient = ingredients
+            .first()
+            .ok_or(MongoRepError::InvalidIngredientHash())?;
+        // only an ongoing recipe takes completions, a completed ingredient or
+        // a log applied again is left as is
+        let filter = doc! {
+            "chain_id": chain_id,
+            "address": address.to_string(),
+            "status": "Ongoing",
+            "ingredients": {"$elemMatch": {
+                "id": ingredient.id,
+                "status": {"$ne": "Completed"},
+                "transaction_hash": {"$ne": completion.transaction_hash},
+            }},
+        };
         match self
             .recipes
             .update_one(
-                doc! {"address": address.to_string(), "ingredients.id": ingredient.id},
-                doc! {"$set": {"last_block": block, "ingredients.$.status": "Completed", "ingredients.$.owner": owner}},
+                filter,
+                doc! {"$set": {
+                    "last_block": completion.block,
+                    "ingredients.$.status": "Pending",
+                    "ingredients.$.owner": completion.owner,
+                    "ingredients.$.block": completion.block,
+                    "ingredients.$.timestamp": completion.timestamp,
+                    "ingredients.$.transaction_hash": completion.transaction_hash,
+                    "ingredients.$.confirmations": 0,
+                }},
                 None,
             )
+            .await
             .map_err(MongoRepError::from)
         {
-            Ok(_) => Ok(true),
+            Ok(result) => Ok(result.matched_count > 0),
             Err(_) => Err(MongoRepError::InvalidUpdate(address.to_string())),
         }
     }
 
-    pub fn update_recipe_completed(&self, address: &str) -> Result<bool, MongoRepError> {
-        let recipe = self.get_recipe(address)?;
-        let completed = recipe
-            .ingredients
-            .into_iter()
-            .map(|x| x.status == Status::Completed)
-            .fold(true, |acc, x| acc && x);
-        if !completed {
+    async fn confirm_ingredients(
+        &self,
+        chain_id: i64,
+        head: i64,
+        confirmations: i64,
+    ) -> Result<usize, MongoRepError> {
+        self.chains
+            .update_one(
+                doc! {"chain_id": chain_id},
+                doc! {"$set": {"confirmations": confirmations}},
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        let cursor = self
+            .recipes
+            .find(
+                doc! {"chain_id": chain_id, "ingredients.status": "Pending"},
+                None,
+            )
+            .await
+            .map_err(MongoRepError::from)?;
+        let recipes = cursor
+            .try_collect::<Vec<Recipe>>()
+            .await
+            .map_err(MongoRepError::from)?;
+        let mut completed = 0;
+        for recipe in recipes {
+            for ingredient in recipe
+                .ingredients
+                .iter()
+                .filter(|x| x.status == Status::Pending)
+            {
+                let count = (head - ingredient.block + 1).max(0);
+                let status = if count >= confirmations {
+                    completed += 1;
+                    "Completed"
+                } else {
+                    "Pending"
+                };
+                self.recipes
+                    .update_one(
+                        doc! {"chain_id": chain_id, "address": &recipe.address, "ingredients.id": ingredient.id},
+                        doc! {"$set": {"ingredients.$.status": status, "ingredients.$.confirmations": count}},
+                        None,
+                    ).await
+                    .map_err(|_| MongoRepError::InvalidUpdate(recipe.address.to_string()))?;
+            }
+            // paused, cancelled and expired recipes keep their status
+            if recipe.status == Status::Ongoing
+                && self
+                    .update_recipe_completed(chain_id, &recipe.address)
+                    .await?
+            {
+                self.add_recipe_completed_event(chain_id, &recipe.address)
+                    .await?;
+            }
+        }
+        Ok(completed)
+    }
+
+    async fn set_recipe_status(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        status: Status,
+    ) -> Result<bool, MongoRepError> {
+        let recipe = self.get_recipe(chain_id, address).await?;
+        if recipe.status == status {
             return Ok(false);
         }
-        match self
+        if !recipe.status.can_transition_to(status) {
+            return Err(MongoRepError::InvalidTransition(
+                address.to_string(),
+                recipe.status,
+                status,
+            ));
+        }
+        let status_bson =
+            to_bson(&status).map_err(|_| MongoRepError::InvalidUpdate(address.to_string()))?;
+        let previous = to_bson(&recipe.status)
+            .map_err(|_| MongoRepError::InvalidUpdate(address.to_string()))?;
+        // the status is checked again in case it changed in between
+        let result = self
             .recipes
             .update_one(
-                doc! {"address": address.to_string()},
-                doc! {"$set": {"status": "Completed"}},
+                doc! {"chain_id": chain_id, "address": address, "status": previous},
+                doc! {"$set": {"status": status_bson}},
                 None,
             )
-            .map_err(MongoRepError::from)
-        {
-            Ok(_) => Ok(true),
-            Err(_) => Err(MongoRepError::InvalidUpdate(address.to_string())),
+            .await
+            .map_err(|_| MongoRepError::InvalidUpdate(address.to_string()))?;
+        if result.modified_count == 0 {
+            return Ok(false);
+        }
+        // the ingredients may have been completed while the recipe was paused
+        if status == Status::Ongoing && self.update_recipe_completed(chain_id, address).await? {
+            self.add_recipe_completed_event(chain_id, address).await?;
         }
+        Ok(true)
     }
 
-    pub fn get_leaderboard(&self) -> Result<Vec<(String, u32)>, MongoRepError> {
-        let cursor = self.recipes.aggregate(
+    async fn expire_recipes(&self) -> Result<u64, MongoRepError> {
+        let active = doc! {"$in": ["Ongoing", "Paused"]};
+        let update = doc! {"$set": {"status": "Expired"}};
+        let chains = self
+            .recipes
+            .distinct(
+                "chain_id",
+                doc! {"status": active.clone(), "$or": [
+                    {"deadline.block": {"$ne": Bson::Null}},
+                    {"deadline.timestamp": {"$ne": Bson::Null}},
+                ]},
+                None,
+            )
+            .await?;
+        let mut expired = 0;
+        for chain_id in chains.iter().filter_map(|x| x.as_i64()) {
+            // without an indexed block no timestamp deadline can have passed
+            let (number, timestamp) = match self.get_latest_block(chain_id).await? {
+                Some(block) => (block.number, block.timestamp),
+                None => (self.get_last_block(chain_id).await?, 0),
+            };
+            expired += self
+                .recipes
+                .update_many(
+                    doc! {"chain_id": chain_id, "status": active.clone(), "$or": [
+                        {"deadline.block": {"$lt": number}},
+                        {"deadline.timestamp": {"$lt": timestamp}},
+                    ]},
+                    update.clone(),
+                    None,
+                )
+                .await?
+                .modified_count;
+        }
+        Ok(expired)
+    }
+
+    async fn get_leaderboard(
+        &self,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<(Address, u32)>, MongoRepError> {
+        let cursor = self
+            .recipes
+            .aggregate(
+                vec![
+                    doc! {"$match": with_chain(doc! {}, chain_id)},
+                    doc! {"$unwind": "$ingredients"},
+                    doc! {"$match": {"ingredients.status": "Completed"}},
+                    doc! {"$group": {
+                    "_id": "$ingredients.owner",
+                    "count": {
+                      "$sum": 1
+                    }}},
+                    doc! {"$sort" : {
+                    "count" : -1
+                    }},
+                    doc! { "$limit" : 20},
+                ],
+                None,
+            )
+            .await?;
+        Ok(to_leaderboard(cursor.try_collect().await?))
+    }
+
+    async fn get_leaderboard_at(
+        &self,
+        chain_id: i64,
+        block: i64,
+    ) -> Result<Vec<(Address, u32)>, MongoRepError> {
+        let cursor = self.recipe_events.aggregate(
             vec![
-                doc! {"$unwind": "$ingredients"},
-                doc! {"$match": {"ingredients.status": "Completed"}},
+                doc! {"$match": {"chain_id": chain_id, "event": "IngredientCompleted", "block": {"$lte": block}}},
                 doc! {"$group": {
-                "_id": "$ingredients.owner",
+                "_id": "$owner",
                 "count": {
                   "$sum": 1
                 }}},
-                doc! { "$limit" : 20},
                 doc! {"$sort" : {
                 "count" : -1
                 }},
+                doc! { "$limit" : 20},
             ],
             None,
-        )?;
-        match cursor
-            .map(|x| {
-                let doc = x.unwrap_or_default();
-                (
-                    doc.get_str("_id").unwrap().to_string(),
-                    doc.get_i32("count").unwrap() as u32,
-                )
-            })
-            .collect::<Vec<(String, u32)>>()
-        {
-            v => Ok(v),
-            _ => Err(MongoRepError::EmptyResponse()),
-        }
+        ).await?;
+        Ok(to_leaderboard(cursor.try_collect().await?))
     }
 
-    pub fn get_statistics(&self, address: &str) -> Result<Vec<(u32, u32)>, MongoRepError> {
+    async fn get_statistics(
+        &self,
+        address: &Address,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<(u32, u32)>, MongoRepError> {
         let cursor = self.recipes.aggregate(
             vec![
+                doc! {"$match": with_chain(doc! {}, chain_id)},
                 doc! {"$unwind": "$ingredients"},
                 doc! {"$match": {"ingredients.status": "Completed", "ingredients.owner": address}},
+                doc! {"$group": {
+                  "_id": {"chain_id": "$chain_id", "address": "$address"},
+                  "ingredients": { "$sum": 1 }
+                }},
+                doc! {"$group": {
+                    "_id": null,
+                    "recipes": { "$sum": 1 },
+                    "ingredients": { "$sum": "$ingredients" }
+                }},
+            ],
+            None,
+        ).await?;
+        Ok(to_statistics(cursor.try_collect().await?))
+    }
+
+    async fn get_statistics_at(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        block: i64,
+    ) -> Result<Vec<(u32, u32)>, MongoRepError> {
+        let cursor = self.recipe_events.aggregate(
+            vec![
+                doc! {"$match": {"chain_id": chain_id, "event": "IngredientCompleted", "owner": address, "block": {"$lte": block}}},
                 doc! {"$group": {
                   "_id": "$address",
                   "ingredients": { "$sum": 1 }
@@ -264,228 +881,396 @@ impl MongoRep {
                 }},
             ],
             None,
-        )?;
-        match cursor
-            .map(|x| {
-                let doc = x.unwrap_or_default();
-                (
-                    doc.get_i32("recipes").unwrap() as u32,
-                    doc.get_i32("ingredients").unwrap() as u32,
-                )
-            })
-            .collect::<Vec<(u32, u32)>>()
-        {
-            v => Ok(v),
-            _ => Err(MongoRepError::EmptyResponse()),
-        }
+        ).await?;
+        Ok(to_statistics(cursor.try_collect().await?))
     }
 
-    pub fn get_last_block(&self) -> Result<i64, MongoRepError> {
+    async fn get_last_block(&self, chain_id: i64) -> Result<i64, MongoRepError> {
         let find_options = FindOptions::builder()
             .sort(doc! {"last_block": -1})
             .limit(1)
             .build();
         let cursor = self
             .recipes
-            .find(doc! {}, find_options)
+            .find(doc! {"chain_id": chain_id}, find_options)
+            .await
             .map_err(MongoRepError::from)?;
-        match cursor.collect::<Result<Vec<Recipe>, mongoError>>() {
-            Ok(v) if v.len() == 0 => Ok(0),
+        match cursor.try_collect::<Vec<Recipe>>().await {
+            Ok(v) if v.is_empty() => Ok(0),
             Ok(v) => Ok(v.first().unwrap().last_block),
             Err(e) => Err(MongoRepError::QueryError(e)),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
+    async fn add_block(&self, block: &Block) -> Result<bool, MongoRepError> {
+        let option = ReplaceOptions::builder().upsert(true).build();
+        match self
+            .blocks
+            .replace_one(
+                doc! {"chain_id": block.chain_id, "number": block.number},
+                block,
+                option,
+            )
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(_) => Err(MongoRepError::InvalidAddBlock(block.number)),
+        }
+    }
 
-    use crate::infra::mongo::types::Status;
+    async fn get_latest_block(&self, chain_id: i64) -> Result<Option<Block>, MongoRepError> {
+        let find_options = FindOneOptions::builder().sort(doc! {"number": -1}).build();
+        self.blocks
+            .find_one(doc! {"chain_id": chain_id}, find_options)
+            .await
+            .map_err(MongoRepError::from)
+    }
 
-    use super::*;
+    async fn get_confirmations(&self, chain_id: i64) -> Result<Option<i64>, MongoRepError> {
+        Ok(self
+            .chains
+            .find_one(doc! {"chain_id": chain_id}, None)
+            .await?
+            .and_then(|x| x.get_i64("confirmations").ok()))
+    }
 
-    fn init_repo(database: &str) -> MongoRep {
-        MongoRep::init(String::from("mongodb://localhost:27017/"), database).unwrap()
+    async fn get_blocks_since(
+        &self,
+        chain_id: i64,
+        number: i64,
+    ) -> Result<Vec<Block>, MongoRepError> {
+        let find_options = FindOptions::builder().sort(doc! {"number": -1}).build();
+        let cursor = self
+            .blocks
+            .find(
+                doc! {"chain_id": chain_id, "number": {"$gte": number}},
+                find_options,
+            )
+            .await
+            .map_err(MongoRepError::from)?;
+        cursor
+            .try_collect::<Vec<Block>>()
+            .await
+            .map_err(MongoRepError::from)
     }
 
-    #[test]
-    fn test_init_mongo_repo_passes() {
-        init_repo("test");
+    async fn rollback_block(&self, block: &Block) -> Result<bool, MongoRepError> {
+        let chain_id = block.chain_id;
+        for change in block.changes.iter().rev() {
+            match change {
+                BlockChange::RecipeCreated { address } => {
+                    self.recipes
+                        .delete_one(doc! {"chain_id": chain_id, "address": address}, None)
+                        .await
+                        .map_err(|_| MongoRepError::InvalidRollback(block.number))?;
+                }
+                BlockChange::IngredientCompleted { address, hash } => {
+                    self.revert_recipe(chain_id, address, hash, block.number - 1)
+                        .await?;
+                }
+                BlockChange::IngredientTransferred {
+                    hash,
+                    owner,
+                    expiry,
+                } => {
+                    self.restore_ingredient_owner(hash, owner.as_ref(), *expiry)
+                        .await?;
+                }
+            }
+        }
+        // the history only follows the canonical chain
+        self.recipe_events
+            .delete_many(doc! {"chain_id": chain_id, "block": block.number}, None)
+            .await
+            .map_err(|_| MongoRepError::InvalidRollback(block.number))?;
+        match self
+            .blocks
+            .delete_one(doc! {"chain_id": chain_id, "number": block.number}, None)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(_) => Err(MongoRepError::InvalidRollback(block.number)),
+        }
     }
 
-    #[test]
-    fn test_get_last_block_without_data() {
-        let mongo_rep = init_repo("lfb");
-        let block = mongo_rep.get_last_block().unwrap();
-        assert_eq!(block, 0);
+    async fn revert_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hash: &H256,
+        block: i64,
+    ) -> Result<bool, MongoRepError> {
+        let ingredients = self.get_ingredients_by_hash(&[*hash]).await?;
+        let ingredient = ingredients
+            .first()
+            .ok_or(MongoRepError::InvalidIngredientHash())?;
+        // only the completion of the recipe is undone, not the other statuses
+        self.recipes
+            .update_one(
+                doc! {"chain_id": chain_id, "address": address, "status": "Completed"},
+                doc! {"$set": {"status": "Ongoing"}},
+                None,
+            )
+            .await
+            .map_err(|_| MongoRepError::InvalidUpdate(address.to_string()))?;
+        match self
+            .recipes
+            .update_one(
+                doc! {"chain_id": chain_id, "address": address.to_string(), "ingredients.id": ingredient.id},
+                doc! {
+                    "$set": {"ingredients.$.status": "Ongoing", "ingredients.$.owner": Bson::Null, "ingredients.$.timestamp": 0, "ingredients.$.transaction_hash": Bson::Null, "ingredients.$.confirmations": 0},
+                    "$min": {"last_block": block},
+                },
+                None,
+            ).await
+            .map_err(MongoRepError::from)
+        {
+            Ok(_) => Ok(true),
+            Err(_) => Err(MongoRepError::InvalidUpdate(address.to_string())),
+        }
     }
-    #[test]
-    #[should_panic(expected = "InvalidIngredientName")]
-    fn test_get_ingredient_invalid_ingredient_query() {
-        let mongo_rep = init_repo("lfb");
-        mongo_rep.get_ingredient("hello.eth").unwrap();
+
+    async fn add_history_event(&self, event: &HistoryEvent) -> Result<bool, MongoRepError> {
+        let document = to_document(event)
+            .map_err(|_| MongoRepError::InvalidAddHistoryEvent(event.address.to_string()))?;
+        let mut option = UpdateOptions::default();
+        option.upsert = Some(true);
+        match self
+            .recipe_events
+            .update_one(
+                doc! {
+                    "chain_id": event.chain_id,
+                    "address": &event.address,
+                    "transaction_hash": &event.transaction_hash,
+                    "log_index": event.log_index,
+                    "event": document.get_str("event").unwrap_or_default(),
+                },
+                doc! {"$setOnInsert": document},
+                option,
+            )
+            .await
+        {
+            Ok(result) => Ok(result.upserted_id.is_some()),
+            Err(_) => Err(MongoRepError::InvalidAddHistoryEvent(
+                event.address.to_string(),
+            )),
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "EmptyResponse")]
-    fn test_get_ingredients_invalid_ingredient_query() {
-        let mongo_rep = init_repo("lfb");
-        mongo_rep.get_ingredients(vec!["hello.eth"]).unwrap();
+    async fn get_recipe_history(
+        &self,
+        chain_id: i64,
+        address: &Address,
+    ) -> Result<Vec<HistoryEvent>, MongoRepError> {
+        let find_options = FindOptions::builder()
+            .sort(doc! {"block": 1, "log_index": 1, "_id": 1})
+            .build();
+        let cursor = self
+            .recipe_events
+            .find(
+                doc! {"chain_id": chain_id, "address": address},
+                find_options,
+            )
+            .await
+            .map_err(MongoRepError::from)?;
+        cursor
+            .try_collect::<Vec<HistoryEvent>>()
+            .await
+            .map_err(MongoRepError::from)
     }
 
-    #[test]
-    fn test_get_ingredients_passes() {
-        let mongo_rep = init_repo("lfb");
-        let ingredients = mongo_rep
-            .get_ingredients(vec!["abricot.eth", "ail.eth"])
-            .unwrap();
-        assert_eq!(ingredients[0].domain, "abricot.eth");
-        assert_eq!(ingredients[1].domain, "ail.eth");
-    }
-
-    #[test]
-    fn test_get_ingredients_from_hash() {
-        let mongo_rep = init_repo("lfb");
-        let ingredients: Vec<Ingredient> = mongo_rep
-            .get_ingredients_by_hash(vec![
-                "0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e",
-                "0xbb46ee301b409e685fdca2667a94deffe378f7081edb25cee0386dc0cd5c2aca",
-            ])
-            .unwrap();
-        // dbg!(ingredients)
-        assert_eq!(ingredients[0].domain, "abricot.eth");
-        assert_eq!(ingredients[1].domain, "agaragar.eth");
+    async fn rebuild_recipes(&self) -> Result<usize, MongoRepError> {
+        let cursor = self
+            .recipe_events
+            .aggregate(
+                vec![doc! {"$group": {"_id": {"chain_id": "$chain_id", "address": "$address"}}}],
+                None,
+            )
+            .await?;
+        let recipes = cursor
+            .try_collect::<Vec<Document>>()
+            .await
+            .map_err(MongoRepError::from)?;
+        for recipe in recipes.iter() {
+            let key = recipe
+                .get_document("_id")
+                .map_err(|_| MongoRepError::EmptyResponse())?;
+            let address: Address = key
+                .get_str("address")
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .ok_or(MongoRepError::EmptyResponse())?;
+            self.rebuild_recipe(
+                key.get_i64("chain_id").unwrap_or(MAINNET_CHAIN_ID),
+                &address,
+            )
+            .await?;
+        }
+        Ok(recipes.len())
     }
 
-    #[test]
-    #[should_panic(expected = "IncorrectIngredientsLength")]
-    fn test_get_recipe_incorrect_ingredients_list_length() {
-        let mongo_rep = init_repo("lfb");
-        mongo_rep.get_recipes(vec!["hello.eth"]).unwrap();
+    async fn prune_blocks(&self, chain_id: i64, number: i64) -> Result<u64, MongoRepError> {
+        self.blocks
+            .delete_many(doc! {"chain_id": chain_id, "number": {"$lt": number}}, None)
+            .await
+            .map(|x| x.deleted_count)
+            .map_err(MongoRepError::from)
     }
 
-    #[test]
-    #[should_panic(expected = "EmptyResponse")]
-    fn test_get_recipe_invalid_ingredients_query() {
-        let mongo_rep = init_repo("lfb");
-        mongo_rep
-            .get_recipes(vec!["hello.eth", "there.eth"])
-            .unwrap();
+    async fn add_catalog_version(&self, version: &CatalogVersion) -> Result<bool, MongoRepError> {
+        match self.merkle_roots.insert_one(version, None).await {
+            Ok(_) => Ok(true),
+            Err(_) => Err(MongoRepError::InvalidAddCatalogVersion()),
+        }
     }
 
-    #[test]
-    fn test_get_recipe_passes() {
-        let mongo_rep = init_repo("lfb");
-        assert_eq!(
-            "0x1245425523",
-            mongo_rep.get_recipe("0x1245425523").unwrap().address
-        );
+    async fn replace_recipe(&self, recipe: &Recipe) -> Result<bool, MongoRepError> {
+        let option = ReplaceOptions::builder().upsert(true).build();
+        match self
+            .recipes
+            .replace_one(
+                doc! {"chain_id": recipe.chain_id, "address": &recipe.address},
+                recipe,
+                option,
+            )
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(_) => Err(MongoRepError::InvalidUpdate(recipe.address.to_string())),
+        }
     }
+}
 
-    #[test]
-    fn test_get_recipes_passes() {
-        let mongo_rep = init_repo("lfb");
-        let recipe = mongo_rep
-            .get_recipes(vec!["abricot.eth", "ail.eth"])
-            .unwrap();
-        assert_eq!(recipe[0].address, "0x1245425523");
-        assert_eq!(recipe[0].status, Status::Ongoing);
+// round trips every document of the collection through its model, the
+// documents which can not be read are left untouched
+async fn normalize_collection<T>(collection: &Collection<T>) -> Result<u64, MongoRepError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let raw = collection.clone_with_type::<Document>();
+    let mut updated = 0;
+    let mut cursor = raw.find(None, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let id = match document.get("_id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let normalized = match from_document::<T>(document.clone()).map(|x| to_document(&x)) {
+            Ok(Ok(normalized)) => normalized,
+            _ => {
+                println!("could not normalize document {} of {}", id, raw.name());
+                continue;
+            }
+        };
+        // fields unknown to the model are kept
+        let mut replacement = document.clone();
+        replacement.extend(normalized);
+        if replacement != document {
+            raw.replace_one(doc! {"_id": id}, replacement, None).await?;
+            updated += 1;
+        }
     }
+    Ok(updated)
+}
 
-    #[test]
-    fn test_get_recipe_ongoing_passes() {
-        let mongo_rep = init_repo("lfb");
-        let recipe = mongo_rep.get_recipes_ongoing().unwrap();
-        assert_eq!("0x1245425523", recipe[0].address);
-        assert_eq!(Status::Ongoing, recipe[0].status);
+// restricts the filter to the chain when one is given
+fn with_chain(mut filter: Document, chain_id: Option<i64>) -> Document {
+    if let Some(chain_id) = chain_id {
+        filter.insert("chain_id", chain_id);
     }
+    filter
+}
 
-    #[test]
-    fn test_add_recipe_passes() {
-        let mongo_rep = init_repo("lfb");
-        let ingredients = mongo_rep
-            .get_ingredients(vec!["abricot.eth", "ail.eth", "aiguillettedecanard.eth"])
-            .unwrap();
-        assert!(mongo_rep
-            .add_recipe(
-                "0x1245425523",
-                ingredients.iter().map(|x| x.hash.as_str()).collect(),
-                1234,
-            )
-            .unwrap());
-    }
-
-    #[test]
-    fn test_update_recipe_and_complete_passes() {
-        let mongo_rep = init_repo("lfb");
-        // update all ingredients of recipe 0x1245425523
-        assert!(mongo_rep
-            .update_recipe(
-                "0x1245425523",
-                "0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e",
-                "tim",
-                12345,
-            )
-            .unwrap());
-        assert!(mongo_rep
-            .update_recipe(
-                "0x1245425523",
-                "0x3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a",
-                "alice",
-                12346,
-            )
-            .unwrap());
-        assert!(mongo_rep
-            .update_recipe(
-                "0x1245425523",
-                "0x659ede6c695c50ddd8eb948402f1d4164a77fc60d84b7e473eb9058b40444821",
-                "bob",
-                12347,
+fn to_leaderboard(documents: Vec<Document>) -> Vec<(Address, u32)> {
+    documents
+        .into_iter()
+        .filter_map(|doc| {
+            // completed ingredients always have an owner
+            let owner = doc.get_str("_id").ok()?.parse().ok()?;
+            Some((owner, doc.get_i32("count").unwrap() as u32))
+        })
+        .collect::<Vec<(Address, u32)>>()
+}
+
+fn to_statistics(documents: Vec<Document>) -> Vec<(u32, u32)> {
+    documents
+        .into_iter()
+        .map(|doc| {
+            (
+                doc.get_i32("recipes").unwrap() as u32,
+                doc.get_i32("ingredients").unwrap() as u32,
             )
-            .unwrap());
+        })
+        .collect::<Vec<(u32, u32)>>()
+}
 
-        mongo_rep.update_recipe_completed("0x1245425523").unwrap();
-        let recipe = mongo_rep.get_recipe("0x1245425523").unwrap();
-        assert_eq!(Status::Completed, recipe.status);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::merkle::get_namehash;
+    use crate::infra::mongo::suite::repository_suite;
 
-    #[test]
-    fn test_get_leaderboard() {
-        let mongo_rep = init_repo("lfb");
-        let leaderboard = mongo_rep.get_leaderboard().unwrap();
-        // TODO update to assert_eq!
-        dbg!(leaderboard);
+    // each test gets its own database as the tests run concurrently, the
+    // `lfb_test_*` databases are left behind for inspection
+    async fn catalog(domains: &[&str]) -> MongoRep {
+        let database = format!("lfb_test_{}", ObjectId::new());
+        let rep = MongoRep::init(String::from("mongodb://localhost:27017/"), &database)
+            .await
+            .unwrap();
+        rep.create_indexes().await.unwrap();
+        let ingredients = domains.iter().map(|domain| Ingredient {
+            id: None,
+            domain: domain.to_string(),
+            hash: H256(get_namehash(domain.to_string())),
+            path: vec![],
+            owner: None,
+            expiry: 0,
+        });
+        rep.ingredients
+            .insert_many(ingredients, None)
+            .await
+            .unwrap();
+        rep
     }
 
-    #[test]
-    fn test_get_statistics() {
-        let mongo_rep = init_repo("lfb");
-        let stats = mongo_rep
-            .get_statistics("0xc5e4ec0073631fa872334749381e4d514da130f8")
+    repository_suite!(catalog, #[ignore = "needs a MongoDB on localhost:27017"]);
+
+    #[rocket::async_test]
+    #[ignore = "needs a MongoDB on localhost:27017"]
+    async fn test_catalog_tree_is_rebuilt_when_the_catalog_changes() {
+        let rep = catalog(&["abricot.eth", "ail.eth"]).await;
+        let tree = rep.get_catalog_tree().await.unwrap();
+        assert!(Arc::ptr_eq(
+            &tree,
+            &rep.clone().get_catalog_tree().await.unwrap()
+        ));
+        rep.ingredients
+            .insert_one(
+                Ingredient {
+                    id: None,
+                    domain: String::from("agaragar.eth"),
+                    hash: H256(get_namehash(String::from("agaragar.eth"))),
+                    path: vec![],
+                    owner: None,
+                    expiry: 0,
+                },
+                None,
+            )
+            .await
             .unwrap();
-        // TODO update to assert_eq!
-        dbg!(stats[0]);
-    }
-
-    #[test]
-    fn test_last_block_passes() {
-        let mongo_rep = init_repo("lfb");
-        let ingredients = mongo_rep
-            .get_ingredients(vec![
-                "abricot.eth",
-                "ail.eth",
-                "agaragar.eth",
-                "aiguillettedecanard.eth",
-            ])
+        let rebuilt = rep.get_catalog_tree().await.unwrap();
+        assert!(!Arc::ptr_eq(&tree, &rebuilt));
+        assert_eq!(rebuilt.leaf_count(), 3);
+        // the tree handed out before keeps answering
+        assert_eq!(tree.leaf_count(), 2);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs a MongoDB on localhost:27017"]
+    async fn test_init_mongo_repo_passes() {
+        let mongo_rep = MongoRep::init(String::from("mongodb://localhost:27017/"), "test")
+            .await
             .unwrap();
-        assert!(mongo_rep
-            .add_recipe(
-                "0x1245425524",
-                ingredients.iter().map(|x| x.hash.as_str()).collect(),
-                12348,
-            )
-            .unwrap());
-        let last_block = mongo_rep.get_last_block().unwrap();
-        assert_eq!(last_block, 12348);
+        mongo_rep.get_last_block(1).await.unwrap();
     }
 }
@@ -0,0 +1,864 @@
+use super::repository::{get_recipe_proofs, parse_ingredient_ids, Repository};
+use super::types::{
+    Block, BlockChange, CatalogVersion, Completion, DbIngredient, Deadline, HistoryEvent,
+    HistoryEventKind, Ingredient, Recipe, Status,
+};
+use super::MongoRepError;
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::{get_merkle_tree, get_namehash, CatalogTree, TreeHashing};
+use mongodb::bson::oid::ObjectId;
+use rocket::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::mem::discriminant;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Repository kept in memory, the collections of `MongoRep` are plain vectors
+/// in insertion order. Used by the tests and the offline tools.
+#[derive(Default)]
+pub struct MemoryRep {
+    pub ingredients: Mutex<Vec<Ingredient>>,
+    pub recipes: Mutex<Vec<Recipe>>,
+    pub merkle_roots: Mutex<Vec<CatalogVersion>>,
+    pub blocks: Mutex<Vec<Block>>,
+    pub recipe_events: Mutex<Vec<HistoryEvent>>,
+    pub catalog_hashing: TreeHashing,
+    // chains whose recipe trees are not hashed with the default hashing
+    pub recipe_hashing: Mutex<HashMap<i64, TreeHashing>>,
+    // confirmations of the indexed chains
+    pub confirmations: Mutex<HashMap<i64, i64>>,
+}
+
+impl MemoryRep {
+    /// Repository whose catalog holds the `domains`, without owners.
+    pub fn with_ingredients(domains: &[&str]) -> Self {
+        let ingredients = domains
+            .iter()
+            .map(|domain| Ingredient {
+                id: Some(ObjectId::new()),
+                domain: domain.to_string(),
+                hash: H256(get_namehash(domain.to_string())),
+                path: vec![],
+                owner: None,
+                expiry: 0,
+            })
+            .collect();
+        MemoryRep {
+            ingredients: Mutex::new(ingredients),
+            ..Default::default()
+        }
+    }
+}
+
+// a panicking test must not poison the repository of the other ones
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn on_chain(recipe: &Recipe, chain_id: Option<i64>) -> bool {
+    chain_id.unwrap_or(recipe.chain_id) == recipe.chain_id
+}
+
+fn to_leaderboard(counts: HashMap<Address, u32>) -> Vec<(Address, u32)> {
+    let mut leaderboard: Vec<(Address, u32)> = counts.into_iter().collect();
+    leaderboard.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    leaderboard.truncate(20);
+    leaderboard
+}
+
+fn to_statistics(counts: HashMap<(i64, Address), u32>) -> Vec<(u32, u32)> {
+    if counts.is_empty() {
+        return vec![];
+    }
+    vec![(counts.len() as u32, counts.values().sum())]
+}
+
+#[async_trait]
+impl Repository for MemoryRep {
+    async fn get_ingredient(&self, name: &str) -> Result<Ingredient, MongoRepError> {
+        lock(&self.ingredients)
+            .iter()
+            .find(|x| x.domain == name)
+            .cloned()
+            .ok_or_else(|| MongoRepError::InvalidIngredientName(String::from(name)))
+    }
+
+    async fn get_ingredients(
+        &self,
+        ingredients: Vec<&str>,
+    ) -> Result<Vec<Ingredient>, MongoRepError> {
+        let found: Vec<Ingredient> = lock(&self.ingredients)
+            .iter()
+            .filter(|x| ingredients.contains(&x.domain.as_str()))
+            .cloned()
+            .collect();
+        match found {
+            v if !v.is_empty() => Ok(v),
+            _ => Err(MongoRepError::EmptyResponse()),
+        }
+    }
+
+    async fn get_all_ingredients(&self) -> Result<Vec<Ingredient>, MongoRepError> {
+        let mut ingredients = lock(&self.ingredients).clone();
+        ingredients.sort_by(|a, b| a.domain.cmp(&b.domain));
+        Ok(ingredients)
+    }
+
+    async fn get_catalog_tree(&self) -> Result<Arc<CatalogTree>, MongoRepError> {
+        let domains = self
+            .get_all_ingredients()
+            .await?
+            .into_iter()
+            .map(|x| x.domain)
+            .collect();
+        Ok(Arc::new(get_merkle_tree(domains, self.catalog_hashing)?))
+    }
+
+    fn catalog_hashing(&self) -> TreeHashing {
+        self.catalog_hashing
+    }
+
+    fn recipe_hashing(&self, chain_id: i64) -> TreeHashing {
+        lock(&self.recipe_hashing)
+            .get(&chain_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_recipe_hashing(&self, chain_id: i64, hashing: TreeHashing) {
+        lock(&self.recipe_hashing).insert(chain_id, hashing);
+    }
+
+    async fn update_ingredients_path(&self) -> Result<usize, MongoRepError> {
+        if lock(&self.ingredients).is_empty() {
+            return Ok(0);
+        }
+        let tree = self.get_catalog_tree().await?;
+        let mut ingredients = lock(&self.ingredients);
+        for ingredient in ingredients.iter_mut() {
+            ingredient.path = tree
+                .get_proof_path(&ingredient.domain)?
+                .iter()
+                .map(|x| x.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| MongoRepError::InvalidAddIngredient(ingredient.domain.clone()))?;
+        }
+        Ok(ingredients.len())
+    }
+
+    async fn add_catalog_version(&self, version: &CatalogVersion) -> Result<bool, MongoRepError> {
+        let mut versions = lock(&self.merkle_roots);
+        // the versions are unique, like the index of the collection
+        if versions.iter().any(|x| x.version == version.version) {
+            return Err(MongoRepError::InvalidAddCatalogVersion());
+        }
+        versions.push(version.clone());
+        Ok(true)
+    }
+
+    async fn get_catalog_version_latest(&self) -> Result<Option<CatalogVersion>, MongoRepError> {
+        Ok(lock(&self.merkle_roots)
+            .iter()
+            .max_by_key(|x| x.version)
+            .cloned())
+    }
+
+    async fn get_catalog_version(&self, version: i64) -> Result<CatalogVersion, MongoRepError> {
+        lock(&self.merkle_roots)
+            .iter()
+            .find(|x| x.version == version)
+            .cloned()
+            .ok_or(MongoRepError::InvalidCatalogVersion(version))
+    }
+
+    async fn get_catalog_versions(&self) -> Result<Vec<CatalogVersion>, MongoRepError> {
+        let mut versions: Vec<CatalogVersion> = lock(&self.merkle_roots).clone();
+        versions.sort_by_key(|x| x.version);
+        Ok(versions)
+    }
+
+    async fn get_ingredients_by_hash(
+        &self,
+        hashes: &[H256],
+    ) -> Result<Vec<Ingredient>, MongoRepError> {
+        Ok(lock(&self.ingredients)
+            .iter()
+            .filter(|x| hashes.contains(&x.hash))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_ingredient_owner(
+        &self,
+        hash: &H256,
+        owner: Option<&Address>,
+        expiry: Option<i64>,
+    ) -> Result<bool, MongoRepError> {
+        if owner.is_none() && expiry.is_none() {
+            return Ok(false);
+        }
+        let mut ingredients = lock(&self.ingredients);
+        let ingredient = match ingredients.iter_mut().find(|x| x.hash == *hash) {
+            Some(ingredient) => ingredient,
+            None => return Ok(false),
+        };
+        if let Some(owner) = owner {
+            ingredient.owner = Some(*owner);
+        }
+        if let Some(expiry) = expiry {
+            ingredient.expiry = expiry;
+        }
+        Ok(true)
+    }
+
+    async fn restore_ingredient_owner(
+        &self,
+        hash: &H256,
+        owner: Option<&Address>,
+        expiry: i64,
+    ) -> Result<bool, MongoRepError> {
+        let mut ingredients = lock(&self.ingredients);
+        match ingredients.iter_mut().find(|x| x.hash == *hash) {
+            Some(ingredient) => {
+                ingredient.owner = owner.copied();
+                ingredient.expiry = expiry;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn get_ingredients_by_owner(
+        &self,
+        owner: &Address,
+    ) -> Result<Vec<Ingredient>, MongoRepError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs() as i64);
+        Ok(lock(&self.ingredients)
+            .iter()
+            .filter(|x| x.owner.as_ref() == Some(owner) && (x.expiry == 0 || x.expiry > now))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_ingredients_by_id(
+        &self,
+        ids: Vec<&str>,
+    ) -> Result<Vec<Ingredient>, MongoRepError> {
+        let ids = parse_ingredient_ids(ids)?;
+        Ok(lock(&self.ingredients)
+            .iter()
+            .filter(|x| ids.iter().any(|id| x.id == Some(*id)))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_recipes(
+        &self,
+        ingredients: Vec<&str>,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<Recipe>, MongoRepError> {
+        let len = ingredients.len();
+        if !(2..6).contains(&len) {
+            return Err(MongoRepError::IncorrectIngredientsLength(len));
+        }
+        let ids: Vec<ObjectId> = self
+            .get_ingredients(ingredients)
+            .await?
+            .into_iter()
+            .filter_map(|x| x.id)
+            .collect();
+        Ok(lock(&self.recipes)
+            .iter()
+            .filter(|x| on_chain(x, chain_id))
+            .filter(|x| {
+                ids.iter()
+                    .all(|id| x.ingredients.iter().any(|i| i.id == *id))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_recipes_at(
+        &self,
+        chain_id: i64,
+        ingredients: Vec<&str>,
+        block: i64,
+    ) -> Result<Vec<Recipe>, MongoRepError> {
+        let len = ingredients.len();
+        if !(2..6).contains(&len) {
+            return Err(MongoRepError::IncorrectIngredientsLength(len));
+        }
+        let hashes: Vec<H256> = self
+            .get_ingredients(ingredients)
+            .await?
+            .into_iter()
+            .map(|x| x.hash)
+            .collect();
+        let created: Vec<Address> = lock(&self.recipe_events)
+            .iter()
+            .filter(|x| x.chain_id == chain_id && x.block <= block)
+            .filter(|x| match &x.kind {
+                HistoryEventKind::RecipeCreated { ingredients, .. } => {
+                    hashes.iter().all(|x| ingredients.contains(x))
+                }
+                _ => false,
+            })
+            .map(|x| x.address)
+            .collect();
+        let mut recipes = vec![];
+        for address in created {
+            if let Some(recipe) = self.get_recipe_at(chain_id, &address, block).await? {
+                recipes.push(recipe);
+            }
+        }
+        Ok(recipes)
+    }
+
+    async fn get_recipe(&self, chain_id: i64, address: &Address) -> Result<Recipe, MongoRepError> {
+        lock(&self.recipes)
+            .iter()
+            .find(|x| x.chain_id == chain_id && x.address == *address)
+            .cloned()
+            .ok_or(MongoRepError::EmptyResponse())
+    }
+
+    async fn get_recipes_ongoing(
+        &self,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<Recipe>, MongoRepError> {
+        Ok(lock(&self.recipes)
+            .iter()
+            .filter(|x| on_chain(x, chain_id) && x.status == Status::Ongoing)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_recipe_addresses(&self, chain_id: i64) -> Result<Vec<Address>, MongoRepError> {
+        Ok(lock(&self.recipes)
+            .iter()
+            .filter(|x| x.chain_id == chain_id)
+            .map(|x| x.address)
+            .collect())
+    }
+
+    async fn add_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hashes: &[H256],
+        block: i64,
+        version: &str,
+        deadline: &Deadline,
+    ) -> Result<bool, MongoRepError> {
+        let mut ingredients = self.get_ingredients_by_hash(hashes).await?;
+        // a recipe missing some of its ingredients could never be completed
+        if hashes
+            .iter()
+            .any(|hash| !ingredients.iter().any(|x| x.hash == *hash))
+        {
+            return Ok(false);
+        }
+        // the leaves of the recipe tree follow the order of the contract
+        ingredients.sort_by_key(|x| hashes.iter().position(|h| *h == x.hash));
+        let hashing = self.recipe_hashing(chain_id);
+        let (root, proofs) = get_recipe_proofs(&ingredients, hashing)?;
+        let mut recipes = lock(&self.recipes);
+        // a recipe created again is left as is
+        if recipes
+            .iter()
+            .any(|x| x.chain_id == chain_id && x.address == *address)
+        {
+            return Ok(true);
+        }
+        recipes.push(Recipe {
+            chain_id,
+            address: *address,
+            status: Status::Ongoing,
+            ingredients: ingredients
+                .iter()
+                .filter_map(|x| x.id)
+                .map(|id| DbIngredient {
+                    id,
+                    status: Status::Ongoing,
+                    owner: None,
+                    block: 0,
+                    timestamp: 0,
+                    transaction_hash: None,
+                    confirmations: 0,
+                })
+                .collect(),
+            last_block: block,
+            root,
+            proofs,
+            version: version.to_string(),
+            deadline: *deadline,
+            hashing,
+        });
+        Ok(true)
+    }
+
+    async fn update_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hash: &H256,
+        completion: &Completion,
+    ) -> Result<bool, MongoRepError> {
+        let ingredients = self.get_ingredients_by_hash(&[*hash]).await?;
+        let ingredient = ingredients
+            .first()
+            .ok_or(MongoRepError::InvalidIngredientHash())?;
+        // only an ongoing recipe takes completions, a completed ingredient or
+        // a log applied again is left as is
+        let updatable = |i: &DbIngredient| {
+            Some(i.id) == ingredient.id
+                && i.status != Status::Completed
+                && i.transaction_hash != Some(completion.transaction_hash)
+        };
+        let mut recipes = lock(&self.recipes);
+        let recipe = recipes.iter_mut().find(|x| {
+            x.chain_id == chain_id
+                && x.address == *address
+                && x.status == Status::Ongoing
+                && x.ingredients.iter().any(updatable)
+        });
+        let recipe = match recipe {
+            Some(recipe) => recipe,
+            None => return Ok(false),
+        };
+        recipe.last_block = completion.block;
+        if let Some(db_ingredient) = recipe.ingredients.iter_mut().find(|x| updatable(x)) {
+            db_ingredient.status = Status::Pending;
+            db_ingredient.owner = Some(completion.owner);
+            db_ingredient.block = completion.block;
+            db_ingredient.timestamp = completion.timestamp;
+            db_ingredient.transaction_hash = Some(completion.transaction_hash);
+            db_ingredient.confirmations = 0;
+        }
+        Ok(true)
+    }
+
+    async fn confirm_ingredients(
+        &self,
+        chain_id: i64,
+        head: i64,
+        confirmations: i64,
+    ) -> Result<usize, MongoRepError> {
+        lock(&self.confirmations).insert(chain_id, confirmations);
+        let mut completed = 0;
+        let mut ongoing = vec![];
+        for recipe in lock(&self.recipes).iter_mut().filter(|x| {
+            x.chain_id == chain_id && x.ingredients.iter().any(|i| i.status == Status::Pending)
+        }) {
+            for ingredient in recipe
+                .ingredients
+                .iter_mut()
+                .filter(|x| x.status == Status::Pending)
+            {
+                ingredient.confirmations = (head - ingredient.block + 1).max(0);
+                if ingredient.confirmations >= confirmations {
+                    ingredient.status = Status::Completed;
+                    completed += 1;
+                }
+            }
+            // paused, cancelled and expired recipes keep their status
+            if recipe.status == Status::Ongoing {
+                ongoing.push(recipe.address);
+            }
+        }
+        for address in ongoing {
+            if self.update_recipe_completed(chain_id, &address).await? {
+                self.add_recipe_completed_event(chain_id, &address).await?;
+            }
+        }
+        Ok(completed)
+    }
+
+    async fn set_recipe_status(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        status: Status,
+    ) -> Result<bool, MongoRepError> {
+        {
+            let mut recipes = lock(&self.recipes);
+            let recipe = recipes
+                .iter_mut()
+                .find(|x| x.chain_id == chain_id && x.address == *address)
+                .ok_or(MongoRepError::EmptyResponse())?;
+            if recipe.status == status {
+                return Ok(false);
+            }
+            if !recipe.status.can_transition_to(status) {
+                return Err(MongoRepError::InvalidTransition(
+                    address.to_string(),
+                    recipe.status,
+                    status,
+                ));
+            }
+            recipe.status = status;
+        }
+        // the ingredients may have been completed while the recipe was paused
+        if status == Status::Ongoing && self.update_recipe_completed(chain_id, address).await? {
+            self.add_recipe_completed_event(chain_id, address).await?;
+        }
+        Ok(true)
+    }
+
+    async fn expire_recipes(&self) -> Result<u64, MongoRepError> {
+        let chains: Vec<i64> = lock(&self.recipes)
+            .iter()
+            .filter(|x| x.deadline.block.is_some() || x.deadline.timestamp.is_some())
+            .map(|x| x.chain_id)
+            .collect();
+        let mut heads = HashMap::new();
+        for chain_id in chains {
+            if heads.contains_key(&chain_id) {
+                continue;
+            }
+            // without an indexed block no timestamp deadline can have passed
+            let head = match self.get_latest_block(chain_id).await? {
+                Some(block) => (block.number, block.timestamp),
+                None => (self.get_last_block(chain_id).await?, 0),
+            };
+            heads.insert(chain_id, head);
+        }
+        let mut expired = 0;
+        for recipe in lock(&self.recipes)
+            .iter_mut()
+            .filter(|x| matches!(x.status, Status::Ongoing | Status::Paused))
+        {
+            let (number, timestamp) = match heads.get(&recipe.chain_id) {
+                Some(head) => *head,
+                None => continue,
+            };
+            let passed = matches!(recipe.deadline.timestamp, Some(x) if x < timestamp)
+                || matches!(recipe.deadline.block, Some(x) if x < number);
+            if passed {
+                recipe.status = Status::Expired;
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+
+    async fn get_leaderboard(
+        &self,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<(Address, u32)>, MongoRepError> {
+        let mut counts = HashMap::new();
+        for recipe in lock(&self.recipes).iter().filter(|x| on_chain(x, chain_id)) {
+            for ingredient in recipe
+                .ingredients
+                .iter()
+                .filter(|x| x.status == Status::Completed)
+            {
+                // completed ingredients always have an owner
+                if let Some(owner) = ingredient.owner {
+                    *counts.entry(owner).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(to_leaderboard(counts))
+    }
+
+    async fn get_leaderboard_at(
+        &self,
+        chain_id: i64,
+        block: i64,
+    ) -> Result<Vec<(Address, u32)>, MongoRepError> {
+        let mut counts = HashMap::new();
+        for event in lock(&self.recipe_events)
+            .iter()
+            .filter(|x| x.chain_id == chain_id && x.block <= block)
+        {
+            if let HistoryEventKind::IngredientCompleted { owner, .. } = &event.kind {
+                *counts.entry(*owner).or_insert(0) += 1;
+            }
+        }
+        Ok(to_leaderboard(counts))
+    }
+
+    async fn get_statistics(
+        &self,
+        address: &Address,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<(u32, u32)>, MongoRepError> {
+        let mut counts = HashMap::new();
+        for recipe in lock(&self.recipes).iter().filter(|x| on_chain(x, chain_id)) {
+            let completed = recipe
+                .ingredients
+                .iter()
+                .filter(|x| x.status == Status::Completed && x.owner.as_ref() == Some(address))
+                .count() as u32;
+            if completed > 0 {
+                counts.insert((recipe.chain_id, recipe.address), completed);
+            }
+        }
+        Ok(to_statistics(counts))
+    }
+
+    async fn get_statistics_at(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        block: i64,
+    ) -> Result<Vec<(u32, u32)>, MongoRepError> {
+        let mut counts = HashMap::new();
+        for event in lock(&self.recipe_events)
+            .iter()
+            .filter(|x| x.chain_id == chain_id && x.block <= block)
+        {
+            if matches!(&event.kind, HistoryEventKind::IngredientCompleted { owner, .. } if owner == address)
+            {
+                *counts.entry((chain_id, event.address)).or_insert(0) += 1;
+            }
+        }
+        Ok(to_statistics(counts))
+    }
+
+    async fn get_last_block(&self, chain_id: i64) -> Result<i64, MongoRepError> {
+        Ok(lock(&self.recipes)
+            .iter()
+            .filter(|x| x.chain_id == chain_id)
+            .map(|x| x.last_block)
+            .max()
+            .unwrap_or(0))
+    }
+
+    async fn add_block(&self, block: &Block) -> Result<bool, MongoRepError> {
+        let mut blocks = lock(&self.blocks);
+        blocks.retain(|x| x.chain_id != block.chain_id || x.number != block.number);
+        blocks.push(block.clone());
+        Ok(true)
+    }
+
+    async fn get_latest_block(&self, chain_id: i64) -> Result<Option<Block>, MongoRepError> {
+        Ok(lock(&self.blocks)
+            .iter()
+            .filter(|x| x.chain_id == chain_id)
+            .max_by_key(|x| x.number)
+            .cloned())
+    }
+
+    async fn get_confirmations(&self, chain_id: i64) -> Result<Option<i64>, MongoRepError> {
+        Ok(lock(&self.confirmations).get(&chain_id).copied())
+    }
+
+    async fn get_blocks_since(
+        &self,
+        chain_id: i64,
+        number: i64,
+    ) -> Result<Vec<Block>, MongoRepError> {
+        let mut blocks: Vec<Block> = lock(&self.blocks)
+            .iter()
+            .filter(|x| x.chain_id == chain_id && x.number >= number)
+            .cloned()
+            .collect();
+        blocks.sort_by_key(|x| std::cmp::Reverse(x.number));
+        Ok(blocks)
+    }
+
+    async fn rollback_block(&self, block: &Block) -> Result<bool, MongoRepError> {
+        let chain_id = block.chain_id;
+        for change in block.changes.iter().rev() {
+            match change {
+                BlockChange::RecipeCreated { address } => {
+                    lock(&self.recipes).retain(|x| x.chain_id != chain_id || x.address != *address);
+                }
+                BlockChange::IngredientCompleted { address, hash } => {
+                    self.revert_recipe(chain_id, address, hash, block.number - 1)
+                        .await?;
+                }
+                BlockChange::IngredientTransferred {
+                    hash,
+                    owner,
+                    expiry,
+                } => {
+                    self.restore_ingredient_owner(hash, owner.as_ref(), *expiry)
+                        .await?;
+                }
+            }
+        }
+        // the history only follows the canonical chain
+        lock(&self.recipe_events).retain(|x| x.chain_id != chain_id || x.block != block.number);
+        lock(&self.blocks).retain(|x| x.chain_id != chain_id || x.number != block.number);
+        Ok(true)
+    }
+
+    async fn revert_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hash: &H256,
+        block: i64,
+    ) -> Result<bool, MongoRepError> {
+        let ingredients = self.get_ingredients_by_hash(&[*hash]).await?;
+        let ingredient = ingredients
+            .first()
+            .ok_or(MongoRepError::InvalidIngredientHash())?;
+        let mut recipes = lock(&self.recipes);
+        let recipe = match recipes
+            .iter_mut()
+            .find(|x| x.chain_id == chain_id && x.address == *address)
+        {
+            Some(recipe) => recipe,
+            None => return Ok(true),
+        };
+        // only the completion of the recipe is undone, not the other statuses
+        if recipe.status == Status::Completed {
+            recipe.status = Status::Ongoing;
+        }
+        if let Some(db_ingredient) = recipe
+            .ingredients
+            .iter_mut()
+            .find(|x| Some(x.id) == ingredient.id)
+        {
+            db_ingredient.status = Status::Ongoing;
+            db_ingredient.owner = None;
+            db_ingredient.timestamp = 0;
+            db_ingredient.transaction_hash = None;
+            db_ingredient.confirmations = 0;
+            recipe.last_block = recipe.last_block.min(block);
+        }
+        Ok(true)
+    }
+
+    async fn add_history_event(&self, event: &HistoryEvent) -> Result<bool, MongoRepError> {
+        let mut events = lock(&self.recipe_events);
+        let recorded = events.iter().any(|x| {
+            x.chain_id == event.chain_id
+                && x.address == event.address
+                && x.transaction_hash == event.transaction_hash
+                && x.log_index == event.log_index
+                && discriminant(&x.kind) == discriminant(&event.kind)
+        });
+        if recorded {
+            return Ok(false);
+        }
+        events.push(event.clone());
+        Ok(true)
+    }
+
+    async fn get_recipe_history(
+        &self,
+        chain_id: i64,
+        address: &Address,
+    ) -> Result<Vec<HistoryEvent>, MongoRepError> {
+        let mut history: Vec<HistoryEvent> = lock(&self.recipe_events)
+            .iter()
+            .filter(|x| x.chain_id == chain_id && x.address == *address)
+            .cloned()
+            .collect();
+        // the sort is stable, events of the same log keep their insertion order
+        history.sort_by_key(|x| (x.block, x.log_index));
+        Ok(history)
+    }
+
+    async fn replace_recipe(&self, recipe: &Recipe) -> Result<bool, MongoRepError> {
+        let mut recipes = lock(&self.recipes);
+        match recipes
+            .iter_mut()
+            .find(|x| x.chain_id == recipe.chain_id && x.address == recipe.address)
+        {
+            Some(stored) => *stored = recipe.clone(),
+            None => recipes.push(recipe.clone()),
+        }
+        Ok(true)
+    }
+
+    async fn rebuild_recipes(&self) -> Result<usize, MongoRepError> {
+        let recipes: BTreeMap<(i64, Address), ()> = lock(&self.recipe_events)
+            .iter()
+            .map(|x| ((x.chain_id, x.address), ()))
+            .collect();
+        for (chain_id, address) in recipes.keys() {
+            self.rebuild_recipe(*chain_id, address).await?;
+        }
+        Ok(recipes.len())
+    }
+
+    async fn prune_blocks(&self, chain_id: i64, number: i64) -> Result<u64, MongoRepError> {
+        let mut blocks = lock(&self.blocks);
+        let len = blocks.len();
+        blocks.retain(|x| x.chain_id != chain_id || x.number >= number);
+        Ok((len - blocks.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::merkle::HashKind;
+    use crate::infra::mongo::suite::{self, repository_suite};
+
+    async fn catalog(domains: &[&str]) -> MemoryRep {
+        MemoryRep::with_ingredients(domains)
+    }
+
+    repository_suite!(catalog);
+
+    async fn init_repo() -> MemoryRep {
+        suite::init_repo(catalog(&suite::CATALOG).await).await
+    }
+
+    #[rocket::async_test]
+    async fn test_update_catalog_version_records_hashing() {
+        let mut rep = init_repo().await;
+        let first = rep.update_catalog_version().await.unwrap().unwrap();
+        assert_eq!(first.hashing, TreeHashing::default());
+        rep.catalog_hashing = TreeHashing::sorted(HashKind::Sha256);
+        let second = rep.update_catalog_version().await.unwrap().unwrap();
+        assert_eq!(second.version, 2);
+        assert_eq!(second.hashing.function, HashKind::Sha256);
+        assert_ne!(first.root, second.root);
+        assert!(second.added.is_empty() && second.removed.is_empty());
+        assert!(rep.update_catalog_version().await.unwrap().is_none());
+    }
+
+    #[rocket::async_test]
+    async fn test_refresh_catalog_records_new_ingredients() {
+        let rep = init_repo().await;
+        let first = rep.refresh_catalog().await.unwrap().unwrap();
+        assert_eq!(first.version, 1);
+        assert_eq!(first.added.len(), 4);
+        lock(&rep.ingredients).push(Ingredient {
+            id: Some(ObjectId::new()),
+            domain: String::from("amande.eth"),
+            hash: H256(get_namehash(String::from("amande.eth"))),
+            path: vec![],
+            owner: None,
+            expiry: 0,
+        });
+        let second = rep.refresh_catalog().await.unwrap().unwrap();
+        assert_eq!(second.version, 2);
+        assert_eq!(second.added, vec![String::from("amande.eth")]);
+        assert!(!rep
+            .get_ingredient("amande.eth")
+            .await
+            .unwrap()
+            .path
+            .is_empty());
+        let versions = rep.get_catalog_versions().await.unwrap();
+        assert_eq!(versions.len(), 2);
+
+        // the domains of a version are rebuilt from the changes up to it
+        lock(&rep.ingredients).retain(|x| x.domain != "abricot.eth");
+        let third = rep.refresh_catalog().await.unwrap().unwrap();
+        assert_eq!(third.removed, vec![String::from("abricot.eth")]);
+        let diff = rep.get_catalog_diff(1, 3).await.unwrap();
+        assert_eq!(diff.added, vec![String::from("amande.eth")]);
+        assert_eq!(diff.removed, vec![String::from("abricot.eth")]);
+        let diff = rep.get_catalog_diff(3, 2).await.unwrap();
+        assert_eq!(diff.added, vec![String::from("abricot.eth")]);
+        assert!(diff.removed.is_empty());
+
+        // a version is only recorded once
+        assert!(matches!(
+            rep.add_catalog_version(&third).await,
+            Err(MongoRepError::InvalidAddCatalogVersion())
+        ));
+    }
+}
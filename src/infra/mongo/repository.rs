@@ -0,0 +1,793 @@
+use super::types::{
+    Block, CatalogDiff, CatalogVersion, Completion, DbIngredient, Deadline, HistoryEvent,
+    HistoryEventKind, Ingredient, LeafProof, Recipe, Status,
+};
+use super::MongoRepError;
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::{
+    get_merkle_tree, keccak256, to_hex, CatalogTree, MerkleError, RecipeTree, TreeHashing,
+};
+use mongodb::bson::oid::ObjectId;
+use rocket::async_trait;
+use std::collections::{BTreeSet, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Storage of the catalog, the recipes and the indexed blocks, implemented by
+/// MongoDB and by an in-memory backend.
+#[async_trait]
+pub trait Repository: Send + Sync {
+    async fn get_ingredient(&self, name: &str) -> Result<Ingredient, MongoRepError>;
+
+    async fn get_ingredients(
+        &self,
+        ingredients: Vec<&str>,
+    ) -> Result<Vec<Ingredient>, MongoRepError>;
+
+    async fn get_all_ingredients(&self) -> Result<Vec<Ingredient>, MongoRepError>;
+
+    async fn get_catalog_tree(&self) -> Result<Arc<CatalogTree>, MongoRepError>;
+
+    /// Hashing of the catalog tree, recorded with each catalog version.
+    fn catalog_hashing(&self) -> TreeHashing;
+
+    /// Hashing of the recipe trees of the chain, the one its recipe contracts
+    /// verify proofs with.
+    fn recipe_hashing(&self, chain_id: i64) -> TreeHashing;
+
+    fn set_recipe_hashing(&self, chain_id: i64, hashing: TreeHashing);
+
+    /// Recomputes the merkle path of every ingredient in the catalog tree.
+    async fn update_ingredients_path(&self) -> Result<usize, MongoRepError>;
+
+    /// Records a new catalog version if the ingredients or the hashing changed
+    /// since the latest one.
+    async fn update_catalog_version(&self) -> Result<Option<CatalogVersion>, MongoRepError> {
+        let domains: Vec<String> = self
+            .get_all_ingredients()
+            .await?
+            .into_iter()
+            .map(|x| x.domain)
+            .collect();
+        let hashing = self.catalog_hashing();
+        let hash = domains_hash(&domains);
+        let latest = self.get_catalog_version_latest().await?;
+        let previous = match &latest {
+            Some(latest) if latest.domains_hash == hash && latest.hashing == hashing => {
+                return Ok(None)
+            }
+            Some(latest) => fold_domains(&self.get_catalog_versions().await?, latest.version),
+            None => vec![],
+        };
+
+        let (root, leaf_count) = if domains.is_empty() {
+            (String::new(), 0)
+        } else {
+            let tree = self.get_catalog_tree().await?;
+            (to_hex(&tree.root()), tree.leaf_count() as i64)
+        };
+        let (added, removed) = diff_domains(&previous, &domains);
+        let version = CatalogVersion {
+            version: latest.map_or(1, |x| x.version + 1),
+            root,
+            leaf_count,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs() as i64),
+            added,
+            removed,
+            domains_hash: hash,
+            hashing,
+        };
+        self.add_catalog_version(&version).await?;
+        Ok(Some(version))
+    }
+
+    async fn add_catalog_version(&self, version: &CatalogVersion) -> Result<bool, MongoRepError>;
+
+    /// Records a new catalog version and updates the ingredients path when
+    /// the catalog changed.
+    async fn refresh_catalog(&self) -> Result<Option<CatalogVersion>, MongoRepError> {
+        let version = self.update_catalog_version().await?;
+        if version.is_some() {
+            self.update_ingredients_path().await?;
+        }
+        Ok(version)
+    }
+
+    async fn get_catalog_version_latest(&self) -> Result<Option<CatalogVersion>, MongoRepError>;
+
+    async fn get_catalog_version(&self, version: i64) -> Result<CatalogVersion, MongoRepError>;
+
+    /// Lists the catalog versions in version order.
+    async fn get_catalog_versions(&self) -> Result<Vec<CatalogVersion>, MongoRepError>;
+
+    async fn get_catalog_diff(&self, from: i64, to: i64) -> Result<CatalogDiff, MongoRepError> {
+        let from = self.get_catalog_version(from).await?;
+        let to = self.get_catalog_version(to).await?;
+        let versions = self.get_catalog_versions().await?;
+        let (added, removed) = diff_domains(
+            &fold_domains(&versions, from.version),
+            &fold_domains(&versions, to.version),
+        );
+        Ok(CatalogDiff {
+            from: from.version,
+            to: to.version,
+            from_root: from.root,
+            to_root: to.root,
+            added,
+            removed,
+        })
+    }
+
+    async fn get_ingredients_by_hash(
+        &self,
+        hashes: &[H256],
+    ) -> Result<Vec<Ingredient>, MongoRepError>;
+
+    /// Sets the registrant and the expiry of the ingredient, the ones left to
+    /// `None` are kept.
+    async fn update_ingredient_owner(
+        &self,
+        hash: &H256,
+        owner: Option<&Address>,
+        expiry: Option<i64>,
+    ) -> Result<bool, MongoRepError>;
+
+    /// Puts back the registrant and the expiry of the ingredient, undoing
+    /// `update_ingredient_owner`.
+    async fn restore_ingredient_owner(
+        &self,
+        hash: &H256,
+        owner: Option<&Address>,
+        expiry: i64,
+    ) -> Result<bool, MongoRepError>;
+
+    /// Ingredients registered to `owner` which have not expired, the ones with
+    /// an unknown expiry are kept.
+    async fn get_ingredients_by_owner(
+        &self,
+        owner: &Address,
+    ) -> Result<Vec<Ingredient>, MongoRepError>;
+
+    async fn get_ingredients_by_id(&self, ids: Vec<&str>)
+        -> Result<Vec<Ingredient>, MongoRepError>;
+
+    async fn get_recipes(
+        &self,
+        ingredients: Vec<&str>,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<Recipe>, MongoRepError>;
+
+    /// Recipes of the chain holding all the `ingredients` as they were at `block`.
+    async fn get_recipes_at(
+        &self,
+        chain_id: i64,
+        ingredients: Vec<&str>,
+        block: i64,
+    ) -> Result<Vec<Recipe>, MongoRepError>;
+
+    /// The recipe as it was at `block`, `None` when not created yet or when its
+    /// ingredients are not all in the catalog.
+    async fn get_recipe_at(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        block: i64,
+    ) -> Result<Option<Recipe>, MongoRepError> {
+        let history = self.get_recipe_history(chain_id, address).await?;
+        let ingredients = self
+            .get_ingredients_by_hash(&history_hashes(&history))
+            .await?;
+        project_recipe_at(&history, &ingredients, self.recipe_hashing(chain_id), block)
+    }
+
+    async fn get_recipe(&self, chain_id: i64, address: &Address) -> Result<Recipe, MongoRepError>;
+
+    async fn get_recipes_ongoing(
+        &self,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<Recipe>, MongoRepError>;
+
+    /// Addresses of every recipe of the chain, whatever their status.
+    async fn get_recipe_addresses(&self, chain_id: i64) -> Result<Vec<Address>, MongoRepError>;
+
+    /// Returns false when one of the ingredients is not in the catalog.
+    async fn add_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hashes: &[H256],
+        block: i64,
+        version: &str,
+        deadline: &Deadline,
+    ) -> Result<bool, MongoRepError>;
+
+    /// Marks an ingredient of the recipe as pending, returns false when the
+    /// recipe is not ongoing or the ingredient is already completed or pending
+    /// with the same transaction.
+    async fn update_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hash: &H256,
+        completion: &Completion,
+    ) -> Result<bool, MongoRepError>;
+
+    /// Updates the confirmation count of the pending ingredients of the chain at
+    /// its head `head`, the ones with enough confirmations are completed. The
+    /// `confirmations` are recorded as the ones of the chain.
+    /// Returns the number of completed ingredients.
+    async fn confirm_ingredients(
+        &self,
+        chain_id: i64,
+        head: i64,
+        confirmations: i64,
+    ) -> Result<usize, MongoRepError>;
+
+    /// Completes the recipe once all its ingredients are completed, returns
+    /// whether it was completed.
+    async fn update_recipe_completed(
+        &self,
+        chain_id: i64,
+        address: &Address,
+    ) -> Result<bool, MongoRepError> {
+        let recipe = self.get_recipe(chain_id, address).await?;
+        let completed = recipe
+            .ingredients
+            .iter()
+            .all(|x| x.status == Status::Completed);
+        if !completed {
+            return Ok(false);
+        }
+        self.set_recipe_status(chain_id, address, Status::Completed)
+            .await
+    }
+
+    /// Moves the recipe to `status`, the transitions a recipe can not make are
+    /// rejected. Returns whether the status changed.
+    async fn set_recipe_status(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        status: Status,
+    ) -> Result<bool, MongoRepError>;
+
+    /// Expires the ongoing and paused recipes whose deadline passed, deadlines
+    /// are compared to the number and timestamp of the latest indexed block of
+    /// their chain. Returns the number of expired recipes.
+    async fn expire_recipes(&self) -> Result<u64, MongoRepError>;
+
+    async fn get_leaderboard(
+        &self,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<(Address, u32)>, MongoRepError>;
+
+    /// Leaderboard of the chain as of `block`, from the history of the recipes.
+    async fn get_leaderboard_at(
+        &self,
+        chain_id: i64,
+        block: i64,
+    ) -> Result<Vec<(Address, u32)>, MongoRepError>;
+
+    async fn get_statistics(
+        &self,
+        address: &Address,
+        chain_id: Option<i64>,
+    ) -> Result<Vec<(u32, u32)>, MongoRepError>;
+
+    /// Statistics of `address` on the chain as of `block`, from the history of
+    /// the recipes.
+    async fn get_statistics_at(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        block: i64,
+    ) -> Result<Vec<(u32, u32)>, MongoRepError>;
+
+    async fn get_last_block(&self, chain_id: i64) -> Result<i64, MongoRepError>;
+
+    async fn add_block(&self, block: &Block) -> Result<bool, MongoRepError>;
+
+    async fn get_latest_block(&self, chain_id: i64) -> Result<Option<Block>, MongoRepError>;
+
+    /// Confirmations before the indexer of the chain completes an ingredient,
+    /// as recorded by its last `confirm_ingredients`. `None` when the chain is
+    /// not indexed.
+    async fn get_confirmations(&self, chain_id: i64) -> Result<Option<i64>, MongoRepError>;
+
+    /// Most recent block of the chain whose events are confirmed, `None` when
+    /// the chain is not indexed yet.
+    async fn get_finalized_block(&self, chain_id: i64) -> Result<Option<i64>, MongoRepError> {
+        let confirmations = match self.get_confirmations(chain_id).await? {
+            Some(confirmations) => confirmations,
+            None => return Ok(None),
+        };
+        Ok(self
+            .get_latest_block(chain_id)
+            .await?
+            .map(|x| x.number - confirmations))
+    }
+
+    /// Rejects a point-in-time query past the finalized block of the chain, its
+    /// answer could still change.
+    async fn check_finalized(&self, chain_id: i64, block: i64) -> Result<(), MongoRepError> {
+        match self.get_finalized_block(chain_id).await? {
+            Some(finalized) if block <= finalized => Ok(()),
+            _ => Err(MongoRepError::BlockNotFinalized(block)),
+        }
+    }
+
+    /// Indexed blocks of the chain from the most recent one down to `number`
+    /// included.
+    async fn get_blocks_since(
+        &self,
+        chain_id: i64,
+        number: i64,
+    ) -> Result<Vec<Block>, MongoRepError>;
+
+    /// Reverts the changes applied by an orphaned block and forgets the block.
+    async fn rollback_block(&self, block: &Block) -> Result<bool, MongoRepError>;
+
+    /// Marks an ingredient of the recipe as ongoing again, undoing `update_recipe`.
+    async fn revert_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+        hash: &H256,
+        block: i64,
+    ) -> Result<bool, MongoRepError>;
+
+    /// Appends an event to the history of its recipe, an event already
+    /// recorded for the same log is ignored. Returns whether it was added.
+    async fn add_history_event(&self, event: &HistoryEvent) -> Result<bool, MongoRepError>;
+
+    /// Records the completion of a recipe with the log of its last ingredient.
+    async fn add_recipe_completed_event(
+        &self,
+        chain_id: i64,
+        address: &Address,
+    ) -> Result<bool, MongoRepError> {
+        let last = self
+            .get_recipe_history(chain_id, address)
+            .await?
+            .into_iter()
+            .rev()
+            .find(|x| matches!(x.kind, HistoryEventKind::IngredientCompleted { .. }));
+        match last {
+            Some(last) => {
+                self.add_history_event(&HistoryEvent {
+                    kind: HistoryEventKind::RecipeCompleted,
+                    ..last
+                })
+                .await
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// History of the recipe, in chain order.
+    async fn get_recipe_history(
+        &self,
+        chain_id: i64,
+        address: &Address,
+    ) -> Result<Vec<HistoryEvent>, MongoRepError>;
+
+    /// Replaces the recipe by the projection of its history. The paused,
+    /// cancelled and expired statuses are not part of the history and are kept.
+    async fn rebuild_recipe(
+        &self,
+        chain_id: i64,
+        address: &Address,
+    ) -> Result<Recipe, MongoRepError> {
+        let history = self.get_recipe_history(chain_id, address).await?;
+        let ingredients = self
+            .get_ingredients_by_hash(&history_hashes(&history))
+            .await?;
+        let mut recipe = project_recipe(&history, &ingredients, self.recipe_hashing(chain_id))?
+            .ok_or(MongoRepError::EmptyResponse())?;
+        match self.get_recipe(chain_id, address).await {
+            Ok(Recipe {
+                status: status @ (Status::Paused | Status::Cancelled | Status::Expired),
+                ..
+            }) => recipe.status = status,
+            Ok(_) | Err(MongoRepError::EmptyResponse()) => {}
+            Err(e) => return Err(e),
+        }
+        self.replace_recipe(&recipe).await?;
+        Ok(recipe)
+    }
+
+    /// Stores the recipe in place of the one with the same chain and address.
+    async fn replace_recipe(&self, recipe: &Recipe) -> Result<bool, MongoRepError>;
+
+    /// Replaces every recipe with a history by its projection, returns the
+    /// number of rebuilt recipes.
+    async fn rebuild_recipes(&self) -> Result<usize, MongoRepError>;
+
+    /// Forgets the blocks of the chain older than `number`, they can no longer
+    /// be reorganized.
+    async fn prune_blocks(&self, chain_id: i64, number: i64) -> Result<u64, MongoRepError>;
+}
+
+// ids of the ingredients, any malformed id rejects the whole list
+pub(crate) fn parse_ingredient_ids(ids: Vec<&str>) -> Result<Vec<ObjectId>, MongoRepError> {
+    ids.into_iter()
+        .map(|x| {
+            ObjectId::from_str(x).map_err(|_| MongoRepError::InvalidIngredientId(x.to_string()))
+        })
+        .collect()
+}
+
+// hash of the sorted domains of the catalog
+pub(crate) fn domains_hash(domains: &[String]) -> String {
+    to_hex(&keccak256(domains.join("\n").as_bytes()))
+}
+
+// domains of the catalog at `version`, applying the changes of every version
+// up to it in order
+pub(crate) fn fold_domains(versions: &[CatalogVersion], version: i64) -> Vec<String> {
+    let mut domains = BTreeSet::new();
+    for x in versions.iter().filter(|x| x.version <= version) {
+        for removed in &x.removed {
+            domains.remove(removed);
+        }
+        domains.extend(x.added.iter().cloned());
+    }
+    domains.into_iter().collect()
+}
+
+// returns the domains added and removed to go from `from` to `to`
+pub(crate) fn diff_domains(from: &[String], to: &[String]) -> (Vec<String>, Vec<String>) {
+    let from_set: HashSet<&String> = from.iter().collect();
+    let to_set: HashSet<&String> = to.iter().collect();
+    let added = to
+        .iter()
+        .filter(|x| !from_set.contains(x))
+        .cloned()
+        .collect();
+    let removed = from
+        .iter()
+        .filter(|x| !to_set.contains(x))
+        .cloned()
+        .collect();
+    (added, removed)
+}
+
+// folds the history of a recipe into its state, `ingredients` holds the
+// ingredients the history refers to
+pub(crate) fn project_recipe(
+    history: &[HistoryEvent],
+    ingredients: &[Ingredient],
+    hashing: TreeHashing,
+) -> Result<Option<Recipe>, MongoRepError> {
+    let find = |hash: &H256| ingredients.iter().find(|x| x.hash == *hash);
+    let mut recipe: Option<Recipe> = None;
+    for event in history {
+        match (&event.kind, recipe.as_mut()) {
+            (
+                HistoryEventKind::RecipeCreated {
+                    ingredients,
+                    version,
+                    deadline,
+                },
+                None,
+            ) => {
+                let ingredients: Vec<Ingredient> =
+                    ingredients.iter().filter_map(&find).cloned().collect();
+                let (root, proofs) = get_recipe_proofs(&ingredients, hashing)?;
+                recipe = Some(Recipe {
+                    chain_id: event.chain_id,
+                    address: event.address,
+                    status: Status::Ongoing,
+                    ingredients: ingredients
+                        .iter()
+                        .filter_map(|x| x.id)
+                        .map(|id| DbIngredient {
+                            id,
+                            status: Status::Ongoing,
+                            owner: None,
+                            block: 0,
+                            timestamp: 0,
+                            transaction_hash: None,
+                            confirmations: 0,
+                        })
+                        .collect(),
+                    last_block: event.block,
+                    root,
+                    proofs,
+                    version: version.clone(),
+                    deadline: *deadline,
+                    hashing,
+                });
+            }
+            (
+                HistoryEventKind::IngredientCompleted {
+                    ingredient,
+                    owner,
+                    timestamp,
+                },
+                Some(recipe),
+            ) => {
+                let id = find(ingredient).and_then(|x| x.id);
+                if let Some(x) = recipe.ingredients.iter_mut().find(|x| Some(x.id) == id) {
+                    x.status = Status::Pending;
+                    x.owner = Some(*owner);
+                    x.block = event.block;
+                    x.timestamp = *timestamp;
+                    x.transaction_hash = Some(event.transaction_hash);
+                    x.confirmations = 0;
+                }
+                recipe.last_block = event.block;
+            }
+            (HistoryEventKind::RecipeCompleted, Some(recipe)) => {
+                recipe.status = Status::Completed;
+                for x in recipe.ingredients.iter_mut() {
+                    x.status = Status::Completed;
+                }
+            }
+            // a recipe is only created once and nothing happens before
+            _ => {}
+        }
+    }
+    Ok(recipe)
+}
+
+// the recipe at `block`, ingredients found up to `block` are considered final
+pub(crate) fn project_recipe_at(
+    history: &[HistoryEvent],
+    ingredients: &[Ingredient],
+    hashing: TreeHashing,
+    block: i64,
+) -> Result<Option<Recipe>, MongoRepError> {
+    let history: Vec<HistoryEvent> = history
+        .iter()
+        .filter(|x| x.block <= block)
+        .cloned()
+        .collect();
+    let mut recipe = match project_recipe(&history, ingredients, hashing)? {
+        Some(recipe) => recipe,
+        None => return Ok(None),
+    };
+    // without all of its ingredients the recipe would wrongly look completed
+    let created = history.iter().find_map(|x| match &x.kind {
+        HistoryEventKind::RecipeCreated { ingredients, .. } => Some(ingredients.len()),
+        _ => None,
+    });
+    if recipe.ingredients.is_empty() || Some(recipe.ingredients.len()) != created {
+        return Ok(None);
+    }
+    for x in recipe.ingredients.iter_mut() {
+        if x.status == Status::Pending {
+            x.status = Status::Completed;
+            x.confirmations = block - x.block + 1;
+        }
+    }
+    if recipe
+        .ingredients
+        .iter()
+        .all(|x| x.status == Status::Completed)
+    {
+        recipe.status = Status::Completed;
+    }
+    Ok(Some(recipe))
+}
+
+// hashes of the ingredients the history refers to
+pub(crate) fn history_hashes(history: &[HistoryEvent]) -> Vec<H256> {
+    history
+        .iter()
+        .flat_map(|x| match &x.kind {
+            HistoryEventKind::RecipeCreated { ingredients, .. } => ingredients.as_slice(),
+            _ => &[],
+        })
+        .copied()
+        .collect()
+}
+
+pub(crate) fn get_recipe_proofs(
+    ingredients: &[Ingredient],
+    hashing: TreeHashing,
+) -> Result<(Option<H256>, Vec<LeafProof>), MongoRepError> {
+    if ingredients.is_empty() {
+        return Ok((None, vec![]));
+    }
+    let tree: RecipeTree = get_merkle_tree(
+        ingredients.iter().map(|x| x.domain.clone()).collect(),
+        hashing,
+    )?;
+    let proofs = ingredients
+        .iter()
+        .map(|x| {
+            // the leaf is the ingredient hash unless the tree hashes with
+            // another function
+            Ok(LeafProof {
+                hash: H256(hashing.namehash(x.domain.clone())),
+                path: tree.gen_proof(&x.domain)?.into_iter().map(H256).collect(),
+            })
+        })
+        .collect::<Result<Vec<LeafProof>, MerkleError>>()?;
+    Ok((Some(H256(tree.root())), proofs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::merkle::{get_namehash, HashKind, HashMode};
+    use mongodb::bson::oid::ObjectId;
+
+    #[test]
+    fn test_project_recipe() {
+        let ingredients: Vec<Ingredient> = ["abricot.eth", "ail.eth"]
+            .iter()
+            .map(|domain| Ingredient {
+                id: Some(ObjectId::new()),
+                domain: domain.to_string(),
+                hash: H256(get_namehash(domain.to_string())),
+                path: vec![],
+                owner: None,
+                expiry: 0,
+            })
+            .collect();
+        let event = |block: i64, kind: HistoryEventKind| HistoryEvent {
+            chain_id: 1,
+            address: Address([0xaa; 20]),
+            block,
+            transaction_hash: H256([0x01; 32]),
+            log_index: 0,
+            kind,
+        };
+        let completed = |ingredient: &Ingredient, block: i64| {
+            event(
+                block,
+                HistoryEventKind::IngredientCompleted {
+                    ingredient: ingredient.hash,
+                    owner: Address([0xbb; 20]),
+                    timestamp: block * 12,
+                },
+            )
+        };
+        let hashing = TreeHashing::default();
+        let mut history = vec![
+            completed(&ingredients[0], 1),
+            event(
+                2,
+                HistoryEventKind::RecipeCreated {
+                    ingredients: vec![ingredients[1].hash, ingredients[0].hash],
+                    version: String::from("v1"),
+                    deadline: Deadline {
+                        block: Some(100),
+                        timestamp: None,
+                    },
+                },
+            ),
+            completed(&ingredients[0], 3),
+        ];
+        let recipe = project_recipe(&history, &ingredients, hashing)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipe.status, Status::Ongoing);
+        assert_eq!(recipe.last_block, 3);
+        assert_eq!(recipe.ingredients[0].id, ingredients[1].id.unwrap());
+        assert_eq!(recipe.ingredients[0].status, Status::Ongoing);
+        assert_eq!(recipe.ingredients[1].status, Status::Pending);
+        assert_eq!(recipe.ingredients[1].owner, Some(Address([0xbb; 20])));
+        assert_eq!(recipe.ingredients[1].block, 3);
+        assert_eq!(recipe.ingredients[1].timestamp, 36);
+        assert_eq!(
+            recipe.ingredients[1].transaction_hash,
+            Some(H256([0x01; 32]))
+        );
+        assert_eq!(recipe.proofs[0].hash, ingredients[1].hash);
+        assert_eq!(recipe.version, "v1");
+        assert_eq!(recipe.deadline.block, Some(100));
+
+        // the recipe tree follows the hashing of the chain
+        let sha256 = TreeHashing::sorted(HashKind::Sha256);
+        let other = project_recipe(&history, &ingredients, sha256)
+            .unwrap()
+            .unwrap();
+        assert_eq!(other.hashing, sha256);
+        assert_ne!(other.root, recipe.root);
+        let proof = &other.proofs[0];
+        assert_eq!(proof.hash.0, sha256.namehash(String::from("ail.eth")));
+        let path: Vec<[u8; 32]> = proof.path.iter().map(|x| x.0).collect();
+        assert!(HashKind::Sha256.verify_proof(
+            HashMode::SortedPair,
+            proof.hash.0,
+            &path,
+            0,
+            other.root.unwrap().0
+        ));
+
+        history.push(completed(&ingredients[1], 4));
+        history.push(event(4, HistoryEventKind::RecipeCompleted));
+        let recipe = project_recipe(&history, &ingredients, hashing)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipe.status, Status::Completed);
+        assert!(recipe
+            .ingredients
+            .iter()
+            .all(|x| x.status == Status::Completed));
+
+        assert!(project_recipe(&history[..1], &ingredients, hashing)
+            .unwrap()
+            .is_none());
+
+        // as of block 3 the found ingredient is final but the recipe is not
+        let recipe = project_recipe_at(&history, &ingredients, hashing, 3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipe.status, Status::Ongoing);
+        assert_eq!(recipe.ingredients[1].status, Status::Completed);
+        assert_eq!(recipe.ingredients[1].confirmations, 1);
+        let recipe = project_recipe_at(&history[..3], &ingredients, hashing, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipe.ingredients[1].confirmations, 8);
+        let recipe = project_recipe_at(&history[..4], &ingredients, hashing, 4)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipe.status, Status::Completed);
+        assert!(project_recipe_at(&history, &ingredients, hashing, 1)
+            .unwrap()
+            .is_none());
+        // ingredients missing from the catalog leave nothing to answer with
+        assert!(
+            project_recipe_at(&history[..4], &ingredients[..1], hashing, 4)
+                .unwrap()
+                .is_none()
+        );
+        assert!(project_recipe_at(&history[..4], &[], hashing, 4)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_fold_domains() {
+        let version = |version: i64, added: &[&str], removed: &[&str]| CatalogVersion {
+            version,
+            root: String::new(),
+            leaf_count: 0,
+            timestamp: 0,
+            added: added.iter().map(|x| x.to_string()).collect(),
+            removed: removed.iter().map(|x| x.to_string()).collect(),
+            domains_hash: String::new(),
+            hashing: TreeHashing::default(),
+        };
+        let versions = vec![
+            version(1, &["ail.eth", "abricot.eth"], &[]),
+            version(2, &["agaragar.eth"], &["ail.eth"]),
+            version(3, &["ail.eth"], &[]),
+        ];
+        assert_eq!(
+            vec![String::from("abricot.eth"), String::from("ail.eth")],
+            fold_domains(&versions, 1)
+        );
+        assert_eq!(
+            vec![String::from("abricot.eth"), String::from("agaragar.eth")],
+            fold_domains(&versions, 2)
+        );
+        assert_eq!(fold_domains(&versions, 3).len(), 3);
+        assert_ne!(
+            domains_hash(&fold_domains(&versions, 1)),
+            domains_hash(&fold_domains(&versions, 2))
+        );
+    }
+
+    #[test]
+    fn test_diff_domains() {
+        let from = vec![String::from("abricot.eth"), String::from("ail.eth")];
+        let to = vec![String::from("ail.eth"), String::from("agaragar.eth")];
+        assert_eq!(
+            (
+                vec![String::from("agaragar.eth")],
+                vec![String::from("abricot.eth")]
+            ),
+            diff_domains(&from, &to)
+        );
+        assert_eq!((vec![], vec![]), diff_domains(&from, &from));
+    }
+}
@@ -0,0 +1,817 @@
+//! Behaviour shared by the `Repository` backends, each backend runs these
+//! tests with `repository_suite!` on a repository holding the test catalog.
+
+use super::types::{
+    Block, BlockChange, Completion, Deadline, HistoryEvent, HistoryEventKind, Ingredient,
+};
+use super::{MongoRepError, Repository, Status};
+use crate::infra::eth::{Address, H256};
+use crate::infra::merkle::{
+    get_namehash, to_hex, HashKind, HashMode, Keccak256, TreeHashing, SIZE,
+};
+
+/// Domains of the test catalog.
+pub const CATALOG: [&str; 4] = [
+    "abricot.eth",
+    "ail.eth",
+    "agaragar.eth",
+    "aiguillettedecanard.eth",
+];
+
+// recipe of the test database
+const RECIPE: &str = "0x0000000000000000000000000000001245425523";
+
+/// Runs every test of the suite on the repositories returned by `$catalog`, an
+/// async function building a repository whose catalog holds the given domains.
+/// The attributes, e.g. `#[ignore]`, are added to each test.
+macro_rules! repository_suite {
+    ($catalog:path $(, #[$attr:meta])*) => {
+        repository_suite!(@tests $catalog, [$(#[$attr])*],
+            test_get_last_block_without_data,
+            test_get_ingredient_invalid_ingredient_query,
+            test_get_ingredients_invalid_ingredient_query,
+            test_get_ingredients_passes,
+            test_get_ingredients_by_id,
+            test_update_ingredients_path_passes,
+            test_update_catalog_version_passes,
+            test_get_ingredients_from_hash,
+            test_get_recipe_incorrect_ingredients_list_length,
+            test_get_recipe_invalid_ingredients_query,
+            test_get_recipe_passes,
+            test_get_recipes_passes,
+            test_get_recipe_ongoing_passes,
+            test_add_recipe_passes,
+            test_add_recipe_with_unknown_ingredient,
+            test_add_recipe_stores_merkle_tree,
+            test_update_recipe_and_complete_passes,
+            test_confirm_ingredients_records_confirmations,
+            test_update_recipe_reapplied_log_keeps_completion,
+            test_update_recipe_after_cancellation,
+            test_get_leaderboard,
+            test_get_statistics,
+            test_get_leaderboard_and_statistics_at,
+            test_last_block_passes,
+            test_recipes_are_keyed_by_chain,
+            test_recipe_status_transitions,
+            test_expire_recipes_with_block_deadline,
+            test_rollback_block_reverts_completion
+        );
+    };
+    (@tests $catalog:path, $attrs:tt, $($name:ident),*) => {
+        $(repository_suite!(@test $catalog, $attrs, $name);)*
+    };
+    (@test $catalog:path, [$($attr:tt)*], $name:ident) => {
+        #[rocket::async_test]
+        $($attr)*
+        async fn $name() {
+            let rep = crate::infra::mongo::suite::init_repo(
+                $catalog(&crate::infra::mongo::suite::CATALOG).await,
+            )
+            .await;
+            crate::infra::mongo::suite::$name(&rep).await;
+        }
+    };
+}
+pub(crate) use repository_suite;
+
+fn address(address: &str) -> Address {
+    address.parse().unwrap()
+}
+
+fn hash(hash: &str) -> H256 {
+    hash.parse().unwrap()
+}
+
+fn completion(owner: &str, block: i64) -> Completion {
+    Completion {
+        owner: address(owner),
+        block,
+        timestamp: block * 12,
+        transaction_hash: H256([0x01; 32]),
+    }
+}
+
+/// The test database: the catalog with its paths and a recipe on mainnet.
+pub async fn init_repo<R: Repository>(rep: R) -> R {
+    rep.update_ingredients_path().await.unwrap();
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth", "aiguillettedecanard.eth"])
+        .await
+        .unwrap();
+    rep.add_recipe(
+        1,
+        &address(RECIPE),
+        &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+        1234,
+        "v1",
+        &Deadline::default(),
+    )
+    .await
+    .unwrap();
+    rep
+}
+
+pub async fn test_get_last_block_without_data<R: Repository>(rep: &R) {
+    let block = rep.get_last_block(5).await.unwrap();
+    assert_eq!(block, 0);
+}
+
+pub async fn test_get_ingredient_invalid_ingredient_query<R: Repository>(rep: &R) {
+    assert!(matches!(
+        rep.get_ingredient("hello.eth").await,
+        Err(MongoRepError::InvalidIngredientName(_))
+    ));
+}
+
+pub async fn test_get_ingredients_invalid_ingredient_query<R: Repository>(rep: &R) {
+    assert!(matches!(
+        rep.get_ingredients(vec!["hello.eth"]).await,
+        Err(MongoRepError::EmptyResponse())
+    ));
+}
+
+pub async fn test_get_ingredients_passes<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth"])
+        .await
+        .unwrap();
+    assert_eq!(ingredients[0].domain, "abricot.eth");
+    assert_eq!(ingredients[1].domain, "ail.eth");
+}
+
+pub async fn test_get_ingredients_by_id<R: Repository>(rep: &R) {
+    let ingredient = rep.get_ingredient("abricot.eth").await.unwrap();
+    let id = ingredient.id.unwrap().to_hex();
+    let ingredients = rep.get_ingredients_by_id(vec![&id]).await.unwrap();
+    assert_eq!(ingredients[0].domain, "abricot.eth");
+    assert!(matches!(
+        rep.get_ingredients_by_id(vec![&id, "abricot"]).await,
+        Err(MongoRepError::InvalidIngredientId(x)) if x == "abricot"
+    ));
+}
+
+pub async fn test_update_ingredients_path_passes<R: Repository>(rep: &R) {
+    let count = rep.update_ingredients_path().await.unwrap();
+    let tree = rep.get_catalog_tree().await.unwrap();
+    assert_eq!(count, tree.leaf_count());
+    let ingredient = rep.get_ingredient("abricot.eth").await.unwrap();
+    let path: Vec<String> = ingredient.path.iter().map(|x| x.to_string()).collect();
+    assert_eq!(path, tree.get_proof_path("abricot.eth").unwrap());
+}
+
+pub async fn test_update_catalog_version_passes<R: Repository>(rep: &R) {
+    rep.update_catalog_version().await.unwrap();
+    let latest = rep.get_catalog_version_latest().await.unwrap().unwrap();
+    // the catalog did not change since the latest version
+    assert!(rep.update_catalog_version().await.unwrap().is_none());
+    assert_eq!(
+        latest.root,
+        to_hex(&rep.get_catalog_tree().await.unwrap().root())
+    );
+    let diff = rep
+        .get_catalog_diff(latest.version, latest.version)
+        .await
+        .unwrap();
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+}
+
+pub async fn test_get_ingredients_from_hash<R: Repository>(rep: &R) {
+    let ingredients: Vec<Ingredient> = rep
+        .get_ingredients_by_hash(&[
+            hash("0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e"),
+            hash("0xbb46ee301b409e685fdca2667a94deffe378f7081edb25cee0386dc0cd5c2aca"),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(ingredients[0].domain, "abricot.eth");
+    assert_eq!(ingredients[1].domain, "agaragar.eth");
+}
+
+pub async fn test_get_recipe_incorrect_ingredients_list_length<R: Repository>(rep: &R) {
+    assert!(matches!(
+        rep.get_recipes(vec!["hello.eth"], None).await,
+        Err(MongoRepError::IncorrectIngredientsLength(1))
+    ));
+}
+
+pub async fn test_get_recipe_invalid_ingredients_query<R: Repository>(rep: &R) {
+    assert!(matches!(
+        rep.get_recipes(vec!["hello.eth", "there.eth"], None).await,
+        Err(MongoRepError::EmptyResponse())
+    ));
+}
+
+pub async fn test_get_recipe_passes<R: Repository>(rep: &R) {
+    assert_eq!(
+        address(RECIPE),
+        rep.get_recipe(1, &address(RECIPE)).await.unwrap().address
+    );
+}
+
+pub async fn test_get_recipes_passes<R: Repository>(rep: &R) {
+    let recipe = rep
+        .get_recipes(vec!["abricot.eth", "ail.eth"], None)
+        .await
+        .unwrap();
+    assert_eq!(recipe[0].address, address(RECIPE));
+    assert_eq!(recipe[0].status, Status::Ongoing);
+    assert!(rep
+        .get_recipes(vec!["abricot.eth", "agaragar.eth"], None)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+pub async fn test_get_recipe_ongoing_passes<R: Repository>(rep: &R) {
+    let recipe = rep.get_recipes_ongoing(None).await.unwrap();
+    assert_eq!(address(RECIPE), recipe[0].address);
+    assert_eq!(Status::Ongoing, recipe[0].status);
+}
+
+pub async fn test_add_recipe_passes<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth", "aiguillettedecanard.eth"])
+        .await
+        .unwrap();
+    assert!(rep
+        .add_recipe(
+            1,
+            &address(RECIPE),
+            &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+            1234,
+            "v1",
+            &Deadline::default(),
+        )
+        .await
+        .unwrap());
+    // the recipe is only created once
+    assert_eq!(rep.get_recipe_addresses(1).await.unwrap().len(), 1);
+}
+
+pub async fn test_add_recipe_with_unknown_ingredient<R: Repository>(rep: &R) {
+    let ingredient = rep.get_ingredient("abricot.eth").await.unwrap();
+    assert!(!rep
+        .add_recipe(
+            1,
+            &address("0x0000000000000000000000000000001245425525"),
+            &[
+                ingredient.hash,
+                H256(get_namehash(String::from("amande.eth"))),
+            ],
+            1234,
+            "v1",
+            &Deadline::default(),
+        )
+        .await
+        .unwrap());
+    assert!(rep
+        .get_recipe(1, &address("0x0000000000000000000000000000001245425525"))
+        .await
+        .is_err());
+}
+
+pub async fn test_add_recipe_stores_merkle_tree<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth", "agaragar.eth"])
+        .await
+        .unwrap();
+    assert!(rep
+        .add_recipe(
+            1,
+            &address("0x0000000000000000000000000000001245425525"),
+            &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+            1234,
+            "v1",
+            &Deadline::default(),
+        )
+        .await
+        .unwrap());
+    let recipe = rep
+        .get_recipe(1, &address("0x0000000000000000000000000000001245425525"))
+        .await
+        .unwrap();
+    let root = recipe.root.unwrap().0;
+    assert_eq!(recipe.proofs.len(), 3);
+    for proof in recipe.proofs {
+        let path: Vec<[u8; SIZE]> = proof.path.iter().map(|x| x.0).collect();
+        assert!(HashMode::SortedPair.verify_proof::<Keccak256>(proof.hash.0, &path, 0, root));
+    }
+
+    // the recipes of a chain verifying with sha256 are hashed with it
+    rep.set_recipe_hashing(10, TreeHashing::sorted(HashKind::Sha256));
+    rep.add_recipe(
+        10,
+        &address("0x0000000000000000000000000000001245425525"),
+        &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+        1234,
+        "v1",
+        &Deadline::default(),
+    )
+    .await
+    .unwrap();
+    let recipe = rep
+        .get_recipe(10, &address("0x0000000000000000000000000000001245425525"))
+        .await
+        .unwrap();
+    assert_eq!(recipe.hashing.function, HashKind::Sha256);
+    let root = recipe.root.unwrap().0;
+    for proof in recipe.proofs {
+        let path: Vec<[u8; SIZE]> = proof.path.iter().map(|x| x.0).collect();
+        assert!(HashKind::Sha256.verify_proof(HashMode::SortedPair, proof.hash.0, &path, 0, root));
+    }
+}
+
+pub async fn test_update_recipe_and_complete_passes<R: Repository>(rep: &R) {
+    // update all ingredients of recipe 0x1245425523
+    assert!(rep
+        .update_recipe(
+            1,
+            &address(RECIPE),
+            &hash("0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e"),
+            &completion("0x00000000000000000000000000000000000000aa", 12345),
+        )
+        .await
+        .unwrap());
+    assert!(rep
+        .update_recipe(
+            1,
+            &address(RECIPE),
+            &hash("0x3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a"),
+            &completion("0x00000000000000000000000000000000000000bb", 12346),
+        )
+        .await
+        .unwrap());
+    assert!(rep
+        .update_recipe(
+            1,
+            &address(RECIPE),
+            &hash("0x659ede6c695c50ddd8eb948402f1d4164a77fc60d84b7e473eb9058b40444821"),
+            &completion("0x00000000000000000000000000000000000000cc", 12347),
+        )
+        .await
+        .unwrap());
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    assert_eq!(Status::Ongoing, recipe.status);
+    assert!(recipe
+        .ingredients
+        .iter()
+        .all(|x| x.status == Status::Pending));
+    let first = recipe
+        .ingredients
+        .iter()
+        .find(|x| x.block == 12345)
+        .unwrap();
+    assert_eq!(
+        first.owner,
+        Some(address("0x00000000000000000000000000000000000000aa"))
+    );
+    assert_eq!(first.timestamp, 12345 * 12);
+    assert_eq!(first.transaction_hash, Some(H256([0x01; 32])));
+
+    // the last ingredient only has a single confirmation
+    assert_eq!(rep.confirm_ingredients(1, 12347, 2).await.unwrap(), 2);
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    assert_eq!(Status::Ongoing, recipe.status);
+    let last = recipe
+        .ingredients
+        .iter()
+        .find(|x| x.block == 12347)
+        .unwrap();
+    assert_eq!(last.status, Status::Pending);
+    assert_eq!(last.confirmations, 1);
+
+    assert_eq!(rep.confirm_ingredients(1, 12348, 2).await.unwrap(), 1);
+    rep.update_recipe_completed(1, &address(RECIPE))
+        .await
+        .unwrap();
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    assert_eq!(Status::Completed, recipe.status);
+}
+
+pub async fn test_confirm_ingredients_records_confirmations<R: Repository>(rep: &R) {
+    assert_eq!(rep.get_confirmations(1).await.unwrap(), None);
+    rep.confirm_ingredients(1, 12345, 3).await.unwrap();
+    assert_eq!(rep.get_confirmations(1).await.unwrap(), Some(3));
+    rep.confirm_ingredients(1, 12346, 2).await.unwrap();
+    assert_eq!(rep.get_confirmations(1).await.unwrap(), Some(2));
+    assert_eq!(rep.get_confirmations(10).await.unwrap(), None);
+}
+
+pub async fn test_update_recipe_reapplied_log_keeps_completion<R: Repository>(rep: &R) {
+    let ingredient = hash("0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e");
+    let owner = "0x00000000000000000000000000000000000000aa";
+    assert!(rep
+        .update_recipe(1, &address(RECIPE), &ingredient, &completion(owner, 12345))
+        .await
+        .unwrap());
+    assert_eq!(rep.confirm_ingredients(1, 12350, 2).await.unwrap(), 1);
+
+    // the same log indexed again leaves the completed ingredient as is
+    assert!(!rep
+        .update_recipe(1, &address(RECIPE), &ingredient, &completion(owner, 12345))
+        .await
+        .unwrap());
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    let first = recipe
+        .ingredients
+        .iter()
+        .find(|x| x.block == 12345)
+        .unwrap();
+    assert_eq!(first.status, Status::Completed);
+    assert_eq!(first.confirmations, 6);
+    assert_eq!(rep.confirm_ingredients(1, 12351, 2).await.unwrap(), 0);
+
+    // a pending ingredient is not reset by its own log either
+    let other = hash("0x3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a");
+    let mut pending = completion("0x00000000000000000000000000000000000000bb", 12352);
+    pending.transaction_hash = H256([0x02; 32]);
+    assert!(rep
+        .update_recipe(1, &address(RECIPE), &other, &pending)
+        .await
+        .unwrap());
+    rep.confirm_ingredients(1, 12352, 2).await.unwrap();
+    assert!(!rep
+        .update_recipe(1, &address(RECIPE), &other, &pending)
+        .await
+        .unwrap());
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    let second = recipe
+        .ingredients
+        .iter()
+        .find(|x| x.block == 12352)
+        .unwrap();
+    assert_eq!(second.status, Status::Pending);
+    assert_eq!(second.confirmations, 1);
+}
+
+pub async fn test_get_leaderboard<R: Repository>(rep: &R) {
+    for (ingredient, owner, block) in [
+        (
+            "0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e",
+            "0x00000000000000000000000000000000000000aa",
+            10,
+        ),
+        (
+            "0x659ede6c695c50ddd8eb948402f1d4164a77fc60d84b7e473eb9058b40444821",
+            "0x00000000000000000000000000000000000000bb",
+            11,
+        ),
+        (
+            "0x3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a",
+            "0x00000000000000000000000000000000000000bb",
+            12,
+        ),
+    ] {
+        rep.update_recipe(
+            1,
+            &address(RECIPE),
+            &hash(ingredient),
+            &completion(owner, block),
+        )
+        .await
+        .unwrap();
+    }
+    // pending ingredients are not counted yet
+    assert!(rep.get_leaderboard(None).await.unwrap().is_empty());
+    rep.confirm_ingredients(1, 12, 1).await.unwrap();
+    assert_eq!(
+        rep.get_leaderboard(None).await.unwrap(),
+        vec![
+            (address("0x00000000000000000000000000000000000000bb"), 2),
+            (address("0x00000000000000000000000000000000000000aa"), 1),
+        ]
+    );
+    assert!(rep.get_leaderboard(Some(10)).await.unwrap().is_empty());
+}
+
+pub async fn test_get_statistics<R: Repository>(rep: &R) {
+    let owner = "0xc5e4ec0073631fa872334749381e4d514da130f8";
+    assert!(rep
+        .get_statistics(&address(owner), None)
+        .await
+        .unwrap()
+        .is_empty());
+    rep.update_recipe(
+        1,
+        &address(RECIPE),
+        &hash("0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e"),
+        &completion(owner, 10),
+    )
+    .await
+    .unwrap();
+    rep.confirm_ingredients(1, 10, 1).await.unwrap();
+    let stats = rep.get_statistics(&address(owner), None).await.unwrap();
+    assert_eq!(stats, vec![(1, 1)]);
+}
+
+pub async fn test_get_leaderboard_and_statistics_at<R: Repository>(rep: &R) {
+    let owner = "0x00000000000000000000000000000000000000aa";
+    for (ingredient, owner, block) in [
+        (
+            "0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e",
+            owner,
+            10,
+        ),
+        (
+            "0x659ede6c695c50ddd8eb948402f1d4164a77fc60d84b7e473eb9058b40444821",
+            "0x00000000000000000000000000000000000000bb",
+            11,
+        ),
+        (
+            "0x3e72143cf7e2a5dd27e9d0ad6bd7f09d98b983f9b05e3e57a07d37b385a9504a",
+            owner,
+            12,
+        ),
+    ] {
+        rep.add_history_event(&HistoryEvent {
+            chain_id: 1,
+            address: address(RECIPE),
+            block,
+            transaction_hash: H256([block as u8; 32]),
+            log_index: 0,
+            kind: HistoryEventKind::IngredientCompleted {
+                ingredient: hash(ingredient),
+                owner: address(owner),
+                timestamp: block * 12,
+            },
+        })
+        .await
+        .unwrap();
+    }
+    assert!(rep.get_leaderboard_at(1, 9).await.unwrap().is_empty());
+    assert_eq!(
+        rep.get_leaderboard_at(1, 10).await.unwrap(),
+        vec![(address(owner), 1)]
+    );
+    assert_eq!(
+        rep.get_leaderboard_at(1, 12).await.unwrap(),
+        vec![
+            (address(owner), 2),
+            (address("0x00000000000000000000000000000000000000bb"), 1),
+        ]
+    );
+    assert!(rep.get_leaderboard_at(10, 12).await.unwrap().is_empty());
+    assert_eq!(
+        rep.get_statistics_at(1, &address(owner), 12).await.unwrap(),
+        vec![(1, 2)]
+    );
+    assert!(rep
+        .get_statistics_at(
+            1,
+            &address("0x00000000000000000000000000000000000000bb"),
+            10
+        )
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+pub async fn test_last_block_passes<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec![
+            "abricot.eth",
+            "ail.eth",
+            "agaragar.eth",
+            "aiguillettedecanard.eth",
+        ])
+        .await
+        .unwrap();
+    assert!(rep
+        .add_recipe(
+            1,
+            &address("0x0000000000000000000000000000001245425524"),
+            &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+            12348,
+            "v1",
+            &Deadline::default(),
+        )
+        .await
+        .unwrap());
+    let last_block = rep.get_last_block(1).await.unwrap();
+    assert_eq!(last_block, 12348);
+}
+
+pub async fn test_recipes_are_keyed_by_chain<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth"])
+        .await
+        .unwrap();
+    assert!(rep
+        .add_recipe(
+            10,
+            &address(RECIPE),
+            &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+            99,
+            "v1",
+            &Deadline::default(),
+        )
+        .await
+        .unwrap());
+    // the recipe with the same address on mainnet is left as is
+    assert_eq!(
+        rep.get_recipe(1, &address(RECIPE)).await.unwrap().chain_id,
+        1
+    );
+    let recipe = rep.get_recipe(10, &address(RECIPE)).await.unwrap();
+    assert_eq!(recipe.chain_id, 10);
+    assert_eq!(recipe.ingredients.len(), 2);
+    assert_eq!(rep.get_last_block(10).await.unwrap(), 99);
+    assert_eq!(rep.get_recipes_ongoing(Some(10)).await.unwrap().len(), 1);
+    assert_eq!(rep.get_recipes_ongoing(Some(5)).await.unwrap().len(), 0);
+}
+
+pub async fn test_recipe_status_transitions<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth"])
+        .await
+        .unwrap();
+    let recipe = address(RECIPE);
+    let deadline = Deadline {
+        block: None,
+        timestamp: Some(1000),
+    };
+    rep.add_recipe(
+        20,
+        &recipe,
+        &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+        1,
+        "v1",
+        &deadline,
+    )
+    .await
+    .unwrap();
+    assert!(rep
+        .set_recipe_status(20, &recipe, Status::Paused)
+        .await
+        .unwrap());
+    assert!(matches!(
+        rep.set_recipe_status(20, &recipe, Status::Completed).await,
+        Err(MongoRepError::InvalidTransition(
+            _,
+            Status::Paused,
+            Status::Completed
+        ))
+    ));
+
+    // timestamp deadlines are compared to the indexed head of the chain
+    assert_eq!(rep.expire_recipes().await.unwrap(), 0);
+    rep.add_block(&Block {
+        chain_id: 20,
+        number: 2,
+        hash: H256([0x02; 32]),
+        parent_hash: H256([0x03; 32]),
+        timestamp: 999,
+        changes: vec![],
+    })
+    .await
+    .unwrap();
+    assert_eq!(rep.expire_recipes().await.unwrap(), 0);
+    rep.add_block(&Block {
+        chain_id: 20,
+        number: 3,
+        hash: H256([0x04; 32]),
+        parent_hash: H256([0x02; 32]),
+        timestamp: 2000,
+        changes: vec![],
+    })
+    .await
+    .unwrap();
+    assert_eq!(rep.expire_recipes().await.unwrap(), 1);
+    assert_eq!(
+        rep.get_recipe(20, &recipe).await.unwrap().status,
+        Status::Expired
+    );
+    assert!(matches!(
+        rep.set_recipe_status(20, &recipe, Status::Ongoing).await,
+        Err(MongoRepError::InvalidTransition(..))
+    ));
+}
+
+pub async fn test_update_recipe_after_cancellation<R: Repository>(rep: &R) {
+    assert!(rep
+        .set_recipe_status(1, &address(RECIPE), Status::Cancelled)
+        .await
+        .unwrap());
+    assert!(!rep
+        .update_recipe(
+            1,
+            &address(RECIPE),
+            &hash("0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e"),
+            &completion("0x00000000000000000000000000000000000000aa", 12345),
+        )
+        .await
+        .unwrap());
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    assert_eq!(recipe.status, Status::Cancelled);
+    assert_eq!(recipe.last_block, 1234);
+    assert!(recipe
+        .ingredients
+        .iter()
+        .all(|x| x.status == Status::Ongoing && x.owner.is_none()));
+    // the rejected completion is counted neither now nor as of its block
+    assert!(rep.get_leaderboard(Some(1)).await.unwrap().is_empty());
+    assert!(rep.get_leaderboard_at(1, 12345).await.unwrap().is_empty());
+}
+
+pub async fn test_expire_recipes_with_block_deadline<R: Repository>(rep: &R) {
+    let ingredients = rep
+        .get_ingredients(vec!["abricot.eth", "ail.eth"])
+        .await
+        .unwrap();
+    rep.add_recipe(
+        1,
+        &address("0x0000000000000000000000000000001245425526"),
+        &ingredients.iter().map(|x| x.hash).collect::<Vec<H256>>(),
+        1234,
+        "v1",
+        &Deadline {
+            block: Some(1500),
+            timestamp: None,
+        },
+    )
+    .await
+    .unwrap();
+    // the head of the chain is the last block of its recipes
+    assert_eq!(rep.expire_recipes().await.unwrap(), 0);
+    rep.add_block(&Block {
+        chain_id: 1,
+        number: 1501,
+        hash: H256([0x02; 32]),
+        parent_hash: H256([0x03; 32]),
+        timestamp: 1501 * 12,
+        changes: vec![],
+    })
+    .await
+    .unwrap();
+    assert_eq!(rep.expire_recipes().await.unwrap(), 1);
+    assert_eq!(
+        rep.get_recipe(1, &address(RECIPE)).await.unwrap().status,
+        Status::Ongoing
+    );
+}
+
+pub async fn test_rollback_block_reverts_completion<R: Repository>(rep: &R) {
+    let ingredient = hash("0x8574ea6bd913dd9b95296e9e5cede2d361f64f9b4a2f641b5fae3a2948be331e");
+    let owner = "0x00000000000000000000000000000000000000aa";
+    rep.update_recipe(1, &address(RECIPE), &ingredient, &completion(owner, 1300))
+        .await
+        .unwrap();
+    rep.confirm_ingredients(1, 1300, 1).await.unwrap();
+    rep.add_history_event(&HistoryEvent {
+        chain_id: 1,
+        address: address(RECIPE),
+        block: 1300,
+        transaction_hash: H256([0x01; 32]),
+        log_index: 0,
+        kind: HistoryEventKind::IngredientCompleted {
+            ingredient,
+            owner: address(owner),
+            timestamp: 1300 * 12,
+        },
+    })
+    .await
+    .unwrap();
+    let block = Block {
+        chain_id: 1,
+        number: 1300,
+        hash: H256([0x02; 32]),
+        parent_hash: H256([0x03; 32]),
+        timestamp: 1300 * 12,
+        changes: vec![BlockChange::IngredientCompleted {
+            address: address(RECIPE),
+            hash: ingredient,
+        }],
+    };
+    rep.add_block(&block).await.unwrap();
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    assert_eq!(recipe.last_block, 1300);
+    assert!(recipe
+        .ingredients
+        .iter()
+        .any(|x| x.status == Status::Completed));
+
+    assert!(rep.rollback_block(&block).await.unwrap());
+    let recipe = rep.get_recipe(1, &address(RECIPE)).await.unwrap();
+    assert_eq!(recipe.last_block, 1299);
+    assert!(recipe
+        .ingredients
+        .iter()
+        .all(|x| x.status == Status::Ongoing
+            && x.owner.is_none()
+            && x.transaction_hash.is_none()
+            && x.confirmations == 0));
+    assert!(rep.get_leaderboard(None).await.unwrap().is_empty());
+    assert!(rep
+        .get_recipe_history(1, &address(RECIPE))
+        .await
+        .unwrap()
+        .iter()
+        .all(|x| x.block != 1300));
+    assert!(rep.get_latest_block(1).await.unwrap().is_none());
+
+    // the ingredient can be completed again on the new branch
+    assert!(rep
+        .update_recipe(1, &address(RECIPE), &ingredient, &completion(owner, 1301))
+        .await
+        .unwrap());
+}
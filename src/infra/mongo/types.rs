@@ -1,35 +1,241 @@
+use crate::infra::eth::{empty_as_none, Address, H256};
+use crate::infra::merkle::TreeHashing;
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Chain of the documents written before the chain was recorded.
+pub const MAINNET_CHAIN_ID: i64 = 1;
+
+fn mainnet() -> i64 {
+    MAINNET_CHAIN_ID
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Ingredient {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub domain: String,
-    // TODO change from string to hex string
-    pub hash: String,
-    // TODO change from string to hex string
-    // TODO add merkle tree path
-    pub path: Vec<String>,
+    // namehash of the domain
+    pub hash: H256,
+    // merkle path of the ingredient in the catalog tree
+    #[serde(default)]
+    pub path: Vec<H256>,
+    // registrant of the domain, from the ens registrar logs
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub owner: Option<Address>,
+    // unix timestamp in seconds at which the registration expires, 0 when unknown
+    #[serde(default)]
+    pub expiry: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Recipe {
-    // TODO change from string to hex string
-    pub address: String,
+    // recipes are keyed by chain and address
+    #[serde(default = "mainnet")]
+    pub chain_id: i64,
+    pub address: Address,
     pub status: Status,
     pub ingredients: Vec<DbIngredient>,
     pub last_block: i64,
+    // root of the merkle tree of the recipe ingredients
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub root: Option<H256>,
+    #[serde(default)]
+    pub proofs: Vec<LeafProof>,
+    // version of the recipe contract
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub deadline: Deadline,
+    // hashing of the recipe tree, the one of the verifier of its chain
+    #[serde(default)]
+    pub hashing: TreeHashing,
+}
+
+/// The recipe expires once the chain is past either limit, when set.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Deadline {
+    pub block: Option<i64>,
+    // unix timestamp in seconds
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct LeafProof {
+    pub hash: H256,
+    pub path: Vec<H256>,
+}
+
+/// A version of the catalog tree, recorded each time the ingredients change.
+/// Only the changes are stored, the domains of a version are rebuilt from the
+/// changes of the versions up to it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CatalogVersion {
+    pub version: i64,
+    pub root: String,
+    pub leaf_count: i64,
+    // unix timestamp in seconds
+    pub timestamp: i64,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    // hash of the domains of the catalog, to know when they change
+    #[serde(default)]
+    pub domains_hash: String,
+    // versions recorded before the hashing was configurable are keccak256
+    #[serde(default)]
+    pub hashing: TreeHashing,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct CatalogDiff {
+    pub from: i64,
+    pub to: i64,
+    pub from_root: String,
+    pub to_root: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// A block processed by the indexer, with the changes it applied so that they
+/// can be rolled back when the block is orphaned.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Block {
+    #[serde(default = "mainnet")]
+    pub chain_id: i64,
+    pub number: i64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    #[serde(default)]
+    pub timestamp: i64,
+    #[serde(default)]
+    pub changes: Vec<BlockChange>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind")]
+pub enum BlockChange {
+    RecipeCreated {
+        address: Address,
+    },
+    IngredientCompleted {
+        address: Address,
+        hash: H256,
+    },
+    // holds the owner and the expiry of the ingredient before the change
+    IngredientTransferred {
+        hash: H256,
+        #[serde(default, deserialize_with = "empty_as_none")]
+        owner: Option<Address>,
+        expiry: i64,
+    },
+}
+
+/// An entry of the append-only history of a recipe, the recipe state is the
+/// projection of its history.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HistoryEvent {
+    #[serde(default = "mainnet")]
+    pub chain_id: i64,
+    pub address: Address,
+    pub block: i64,
+    pub transaction_hash: H256,
+    pub log_index: i64,
+    #[serde(flatten)]
+    pub kind: HistoryEventKind,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "event")]
+pub enum HistoryEventKind {
+    RecipeCreated {
+        ingredients: Vec<H256>,
+        #[serde(default)]
+        version: String,
+        #[serde(default)]
+        deadline: Deadline,
+    },
+    IngredientCompleted {
+        ingredient: H256,
+        owner: Address,
+        // unix timestamp in seconds of the block, 0 when unknown
+        #[serde(default)]
+        timestamp: i64,
+    },
+    // recorded once every ingredient is completed, with the log of the last one
+    RecipeCompleted,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DbIngredient {
     pub id: ObjectId,
     pub status: Status,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub owner: Option<Address>,
+    // block in which the ingredient was found
+    #[serde(default)]
+    pub block: i64,
+    // unix timestamp in seconds of `block`, 0 when unknown
+    #[serde(default)]
+    pub timestamp: i64,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub transaction_hash: Option<H256>,
+    // number of blocks on top of and including `block`, while pending
+    #[serde(default)]
+    pub confirmations: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+/// The log completing an ingredient of a recipe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub owner: Address,
+    pub block: i64,
+    // unix timestamp in seconds of the block, 0 when unknown
+    pub timestamp: i64,
+    pub transaction_hash: H256,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
 pub enum Status {
     Ongoing,
+    // found on chain, waiting for enough confirmations
+    Pending,
     Completed,
+    // the statuses below are only given to recipes
+    Cancelled,
+    // the deadline of the recipe passed before it was completed
+    Expired,
+    Paused,
+}
+
+impl Status {
+    /// Whether a recipe can go from this status to `next`, completed, cancelled
+    /// and expired recipes are final.
+    pub fn can_transition_to(self, next: Status) -> bool {
+        use Status::*;
+        matches!(
+            (self, next),
+            (Ongoing, Completed | Cancelled | Expired | Paused)
+                | (Paused, Ongoing | Cancelled | Expired)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recipe_status_transitions() {
+        assert!(Status::Ongoing.can_transition_to(Status::Paused));
+        assert!(Status::Paused.can_transition_to(Status::Ongoing));
+        assert!(Status::Paused.can_transition_to(Status::Expired));
+        // a paused recipe is resumed before it is completed
+        assert!(!Status::Paused.can_transition_to(Status::Completed));
+        for status in [Status::Completed, Status::Cancelled, Status::Expired] {
+            assert!(!status.can_transition_to(Status::Ongoing));
+            assert!(!status.can_transition_to(Status::Cancelled));
+        }
+        assert!(!Status::Ongoing.can_transition_to(Status::Ongoing));
+        assert!(!Status::Ongoing.can_transition_to(Status::Pending));
+    }
 }
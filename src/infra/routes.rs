@@ -1,14 +1,95 @@
-use super::{Ingredient, MongoRep, MongoRepError, Recipe};
-use rocket::get;
+use super::{
+    from_hex, normalize_domain, to_hex, Address, CatalogDiff, CatalogVersion, HashKind, HashMode,
+    HistoryEvent, Ingredient, LeafProof, MerkleError, MongoRepError, Recipe, Repository,
+    TreeHashing, H256, MAINNET_CHAIN_ID,
+};
+use rocket::tokio::task;
+use rocket::{get, post};
 use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    /// ENS domain of the leaf, namehashed before verification
+    pub domain: Option<String>,
+    /// namehash of the leaf, used when no domain is given
+    pub leaf: Option<String>,
+    pub proof: Vec<String>,
+    pub root: String,
+    /// position of the leaf, only needed by the prefixed mode
+    #[serde(default)]
+    pub index: usize,
+    #[serde(default)]
+    pub mode: HashMode,
+    /// hash function of the namehash and of the tree nodes
+    #[serde(default)]
+    pub function: HashKind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub leaf: String,
+    pub valid: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiProofResponse {
+    pub root: String,
+    pub leaves: Vec<String>,
+    pub proof: Vec<String>,
+    pub proof_flags: Vec<bool>,
+    pub hashing: TreeHashing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecipeTreeResponse {
+    pub address: Address,
+    pub root: H256,
+    pub proofs: Vec<LeafProof>,
+    pub hashing: TreeHashing,
+}
+
+fn parse_names(names: &str) -> Vec<&str> {
+    names.split(',').collect()
+}
+
+fn parse_domains(names: &str) -> Result<Vec<String>, Status> {
+    parse_names(names)
+        .into_iter()
+        .map(|x| normalize_domain(x).map_err(|_| Status::BadRequest))
+        .collect()
+}
+
+// malformed addresses and addresses with a wrong checksum are rejected
+fn parse_address(address: &str) -> Result<Address, Status> {
+    address.parse().map_err(|_| Status::BadRequest)
+}
+
+// snapshots only answer from confirmed blocks so that they can be reproduced
+async fn check_finalized(
+    db: &State<Arc<dyn Repository>>,
+    chain_id: i64,
+    block: i64,
+) -> Result<(), Status> {
+    match db.check_finalized(chain_id, block).await {
+        Ok(()) => Ok(()),
+        Err(MongoRepError::BlockNotFinalized(_)) => Err(Status::BadRequest),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
 
 #[get("/ingredient/<name>")]
-pub fn get_ingredient(db: &State<MongoRep>, name: &str) -> Result<Json<Ingredient>, Status> {
-    println!("{}", name);
+pub async fn get_ingredient(
+    db: &State<Arc<dyn Repository>>,
+    name: &str,
+) -> Result<Json<Ingredient>, Status> {
     if name.is_empty() {
         return Err(Status::BadRequest);
     };
-    let ingredient = db.get_ingredient(&name);
+    let name = normalize_domain(name).map_err(|_| Status::BadRequest)?;
+    let ingredient = db.get_ingredient(&name).await;
 
     match ingredient {
         Ok(ingredient) => Ok(Json(ingredient)),
@@ -16,9 +97,34 @@ pub fn get_ingredient(db: &State<MongoRep>, name: &str) -> Result<Json<Ingredien
     }
 }
 
-#[get("/statistics/<addr>")]
-pub fn get_statistics(db: &State<MongoRep>, addr: &str) -> Result<Json<Vec<(u32, u32)>>, Status> {
-    let stats = db.get_statistics(addr);
+#[get("/wallet/<address>/ingredients")]
+pub async fn get_wallet_ingredients(
+    db: &State<Arc<dyn Repository>>,
+    address: &str,
+) -> Result<Json<Vec<Ingredient>>, Status> {
+    let address = parse_address(address)?;
+    match db.get_ingredients_by_owner(&address).await {
+        Ok(ingredients) => Ok(Json(ingredients)),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/statistics/<addr>?<chain>&<block>")]
+pub async fn get_statistics(
+    db: &State<Arc<dyn Repository>>,
+    addr: &str,
+    chain: Option<i64>,
+    block: Option<i64>,
+) -> Result<Json<Vec<(u32, u32)>>, Status> {
+    let addr = parse_address(addr)?;
+    let stats = match block {
+        Some(block) => {
+            let chain = chain.unwrap_or(MAINNET_CHAIN_ID);
+            check_finalized(db, chain, block).await?;
+            db.get_statistics_at(chain, &addr, block).await
+        }
+        None => db.get_statistics(&addr, chain).await,
+    };
 
     match stats {
         Ok(stats) => Ok(Json(stats)),
@@ -26,9 +132,20 @@ pub fn get_statistics(db: &State<MongoRep>, addr: &str) -> Result<Json<Vec<(u32,
     }
 }
 
-#[get("/leaderboard")]
-pub fn get_leaderboard(db: &State<MongoRep>) -> Result<Json<Vec<(String, u32)>>, Status> {
-    let leaderboard = db.get_leaderboard();
+#[get("/leaderboard?<chain>&<block>")]
+pub async fn get_leaderboard(
+    db: &State<Arc<dyn Repository>>,
+    chain: Option<i64>,
+    block: Option<i64>,
+) -> Result<Json<Vec<(Address, u32)>>, Status> {
+    let leaderboard = match block {
+        Some(block) => {
+            let chain = chain.unwrap_or(MAINNET_CHAIN_ID);
+            check_finalized(db, chain, block).await?;
+            db.get_leaderboard_at(chain, block).await
+        }
+        None => db.get_leaderboard(chain).await,
+    };
 
     match leaderboard {
         Ok(leaderboard) => Ok(Json(leaderboard)),
@@ -37,33 +154,50 @@ pub fn get_leaderboard(db: &State<MongoRep>) -> Result<Json<Vec<(String, u32)>>,
 }
 
 #[get("/ingredients/<ids>")]
-pub fn get_ingredients_by_id(
-    db: &State<MongoRep>,
+pub async fn get_ingredients_by_id(
+    db: &State<Arc<dyn Repository>>,
     ids: &str,
 ) -> Result<Json<Vec<Ingredient>>, Status> {
-    let ids = ids.split(',').collect();
-    let result = db.get_ingredients_by_id(ids);
+    let ids = parse_names(ids);
+    let result = db.get_ingredients_by_id(ids).await;
 
     match result {
         Ok(ingredients) => Ok(Json(ingredients)),
         Err(MongoRepError::IncorrectIngredientsLength(_)) => Err(Status::BadRequest),
+        Err(MongoRepError::InvalidIngredientId(_)) => Err(Status::BadRequest),
         Err(_) => Err(Status::InternalServerError),
     }
 }
 
-#[get("/ongoing-recipes")]
-pub fn get_ongoing_recipes(db: &State<MongoRep>) -> Result<Json<Vec<Recipe>>, Status> {
-    let result = db.get_recipes_ongoing();
+#[get("/ongoing-recipes?<chain>")]
+pub async fn get_ongoing_recipes(
+    db: &State<Arc<dyn Repository>>,
+    chain: Option<i64>,
+) -> Result<Json<Vec<Recipe>>, Status> {
+    let result = db.get_recipes_ongoing(chain).await;
     match result {
         Ok(recipes) => Ok(Json(recipes)),
         Err(_) => Err(Status::InternalServerError),
     }
 }
 
-#[get("/recipes/<names>")]
-pub fn get_recipes(db: &State<MongoRep>, names: &str) -> Result<Json<Vec<Recipe>>, Status> {
-    let names = names.split(',').collect();
-    let result = db.get_recipes(names);
+#[get("/recipes/<names>?<chain>&<block>")]
+pub async fn get_recipes(
+    db: &State<Arc<dyn Repository>>,
+    names: &str,
+    chain: Option<i64>,
+    block: Option<i64>,
+) -> Result<Json<Vec<Recipe>>, Status> {
+    let names = parse_domains(names)?;
+    let names = names.iter().map(|x| x.as_str()).collect();
+    let result = match block {
+        Some(block) => {
+            let chain = chain.unwrap_or(MAINNET_CHAIN_ID);
+            check_finalized(db, chain, block).await?;
+            db.get_recipes_at(chain, names, block).await
+        }
+        None => db.get_recipes(names, chain).await,
+    };
 
     match result {
         Ok(recipes) => Ok(Json(recipes)),
@@ -71,3 +205,133 @@ pub fn get_recipes(db: &State<MongoRep>, names: &str) -> Result<Json<Vec<Recipe>
         Err(_) => Err(Status::InternalServerError),
     }
 }
+
+#[post("/verify", data = "<request>")]
+pub async fn verify_merkle_proof(
+    request: Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, Status> {
+    let leaf = match (&request.domain, &request.leaf) {
+        (Some(domain), _) => {
+            let domain = normalize_domain(domain).map_err(|_| Status::BadRequest)?;
+            request.function.namehash(domain)
+        }
+        (_, Some(leaf)) => from_hex(leaf).map_err(|_| Status::BadRequest)?,
+        _ => return Err(Status::BadRequest),
+    };
+    let proof = request
+        .proof
+        .iter()
+        .map(|x| from_hex(x))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Status::BadRequest)?;
+    let root = from_hex(&request.root).map_err(|_| Status::BadRequest)?;
+
+    Ok(Json(VerifyResponse {
+        leaf: to_hex(&leaf),
+        valid: request
+            .function
+            .verify_proof(request.mode, leaf, &proof, request.index, root),
+    }))
+}
+
+#[get("/multiproof/<names>")]
+pub async fn get_multiproof(
+    db: &State<Arc<dyn Repository>>,
+    names: &str,
+) -> Result<Json<MultiProofResponse>, Status> {
+    let names = parse_domains(names)?;
+    let tree = db
+        .get_catalog_tree()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // the proof is read from the tree stored on disk, keep it off the async workers
+    let multiproof = {
+        let tree = tree.clone();
+        task::spawn_blocking(move || {
+            let names: Vec<&str> = names.iter().map(|x| x.as_str()).collect();
+            tree.gen_multiproof(&names)
+        })
+        .await
+        .map_err(|_| Status::InternalServerError)?
+    };
+    match multiproof {
+        Ok(multiproof) => Ok(Json(MultiProofResponse {
+            root: to_hex(&tree.root()),
+            leaves: multiproof.leaves.iter().map(|x| to_hex(x)).collect(),
+            proof: multiproof.proof.iter().map(|x| to_hex(x)).collect(),
+            proof_flags: multiproof.proof_flags,
+            hashing: tree.hashing(),
+        })),
+        Err(MerkleError::MissingLeaf(_)) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/recipe/<address>/merkle?<chain>")]
+pub async fn get_recipe_tree(
+    db: &State<Arc<dyn Repository>>,
+    address: &str,
+    chain: Option<i64>,
+) -> Result<Json<RecipeTreeResponse>, Status> {
+    let address = parse_address(address)?;
+    match db
+        .get_recipe(chain.unwrap_or(MAINNET_CHAIN_ID), &address)
+        .await
+    {
+        Ok(Recipe {
+            address,
+            root: Some(root),
+            proofs,
+            hashing,
+            ..
+        }) => Ok(Json(RecipeTreeResponse {
+            address,
+            root,
+            proofs,
+            hashing,
+        })),
+        Ok(_) | Err(MongoRepError::EmptyResponse()) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/recipe/<address>/history?<chain>")]
+pub async fn get_recipe_history(
+    db: &State<Arc<dyn Repository>>,
+    address: &str,
+    chain: Option<i64>,
+) -> Result<Json<Vec<HistoryEvent>>, Status> {
+    let address = parse_address(address)?;
+    match db
+        .get_recipe_history(chain.unwrap_or(MAINNET_CHAIN_ID), &address)
+        .await
+    {
+        Ok(history) if !history.is_empty() => Ok(Json(history)),
+        Ok(_) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/catalog/versions")]
+pub async fn get_catalog_versions(
+    db: &State<Arc<dyn Repository>>,
+) -> Result<Json<Vec<CatalogVersion>>, Status> {
+    match db.get_catalog_versions().await {
+        Ok(versions) => Ok(Json(versions)),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/catalog/diff/<from>/<to>")]
+pub async fn get_catalog_diff(
+    db: &State<Arc<dyn Repository>>,
+    from: i64,
+    to: i64,
+) -> Result<Json<CatalogDiff>, Status> {
+    match db.get_catalog_diff(from, to).await {
+        Ok(diff) => Ok(Json(diff)),
+        Err(MongoRepError::InvalidCatalogVersion(_)) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
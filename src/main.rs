@@ -1,11 +1,14 @@
-mod infra;
 use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use infra::*;
+use lfb_back::*;
 
 use rocket::config::{CipherSuite, TlsConfig};
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Header;
+use rocket::tokio::{self, runtime::Handle};
 use rocket::{Config, Request, Response};
 
 #[macro_use]
@@ -33,12 +36,63 @@ impl Fairing for CORS {
 }
 
 #[launch]
-fn rocket() -> _ {
-    let db = MongoRep::init(
+async fn rocket() -> _ {
+    let mut db = MongoRep::init(
         dotenv::var("MONGO_URI").expect("MONGO_URI must be set"),
         "lfb",
     )
+    .await
     .unwrap();
+    if let Ok(dir) = dotenv::var("MERKLE_DATA_DIR") {
+        db = db.with_merkle_dir(dir);
+    }
+    if let Ok(function) = dotenv::var("CATALOG_HASH_FUNCTION") {
+        db = db.with_catalog_hashing(TreeHashing::sorted(function.parse().unwrap()));
+    }
+    db.create_indexes().await.unwrap();
+    db.update_ingredients_path().await.unwrap();
+    db.update_catalog_version().await.unwrap();
+    db.set_default_chain_id().await.unwrap();
+    db.normalize_documents().await.unwrap();
+    let db: Arc<dyn Repository> = Arc::new(db);
+
+    // the ingredients are edited outside of the backend, look for changes
+    let catalog = db.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            if let Err(e) = catalog.refresh_catalog().await {
+                println!("could not refresh catalog: {}", e);
+            }
+        }
+    });
+
+    // recipes whose deadline passed can no longer be completed
+    let expiry = db.clone();
+    tokio::spawn(async move {
+        loop {
+            match expiry.expire_recipes().await {
+                Ok(0) => {}
+                Ok(expired) => println!("expired {} recipes", expired),
+                Err(e) => println!("could not expire recipes: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        }
+    });
+
+    // recipes are created and completed on chain, follow the contract logs of each chain,
+    // the rpc client blocks so each indexer keeps its own thread
+    for indexer_config in IndexerConfig::all_from_env() {
+        let indexer_db = db.clone();
+        let runtime = Handle::current();
+        thread::spawn(
+            move || match Indexer::new(indexer_db, runtime, indexer_config) {
+                Ok(indexer) => indexer.run(),
+                Err(e) => println!("could not start indexer: {}", e),
+            },
+        );
+    }
+
     let mut config = Config::debug_default();
     config.address = Ipv4Addr::new(0, 0, 0, 0).into();
     config.port = 8000;
@@ -55,9 +109,16 @@ fn rocket() -> _ {
                 get_ingredient,
                 get_recipes,
                 get_ingredients_by_id,
+                get_wallet_ingredients,
                 get_leaderboard,
                 get_statistics,
-                get_ongoing_recipes
+                get_ongoing_recipes,
+                verify_merkle_proof,
+                get_multiproof,
+                get_recipe_tree,
+                get_recipe_history,
+                get_catalog_versions,
+                get_catalog_diff
             ],
         )
         .attach(CORS)